@@ -0,0 +1,54 @@
+/* Everything the CPU is connected to.  The CPU calls tick() once for every
+ * M-cycle it spends, before the read or write made in that cycle, so an
+ * implementation can clock whatever else it owns in step with it. */
+pub trait Bus {
+    fn read8(&mut self, addr: u16) -> u8;
+    fn write8(&mut self, addr: u16, value: u8);
+    fn tick(&mut self);
+
+    /* STOP was executed, the CPU stays stopped for as long as stopped()
+     * returns true.  By default there is nothing to stop and it carries
+     * straight on. */
+    fn stop(&mut self) {}
+
+    fn stopped(&self) -> bool {
+        false
+    }
+}
+
+/* A flat 64 KiB of RAM and nothing else, for running the CPU on its own
+ * in unit tests and single step conformance suites */
+pub struct RamBus {
+    pub mem: Vec<u8>,
+    /* M-cycles ticked so far */
+    pub cycles: u64,
+}
+
+impl RamBus {
+    pub fn new() -> RamBus {
+        RamBus {
+            mem: vec![0; 0x10000],
+            cycles: 0,
+        }
+    }
+}
+
+impl Default for RamBus {
+    fn default() -> RamBus {
+        RamBus::new()
+    }
+}
+
+impl Bus for RamBus {
+    fn read8(&mut self, addr: u16) -> u8 {
+        self.mem[addr as usize]
+    }
+
+    fn write8(&mut self, addr: u16, value: u8) {
+        self.mem[addr as usize] = value;
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+}
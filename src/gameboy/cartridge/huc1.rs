@@ -0,0 +1,88 @@
+use super::{ram_read, ram_write, rom_read};
+
+/* HuC1, MBC1-like banking plus an infrared LED and sensor
+ * https://gbdev.io/pandocs/HuC1.html
+ */
+pub struct HuC1 {
+    /* 0x0000-0x1FFF, 0x0E maps the IR port over RAM */
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+}
+
+impl HuC1 {
+    pub fn new() -> HuC1 {
+        HuC1 {
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    pub fn read8(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom_read(rom, 0, addr),
+            0x4000 ..= 0x7FFF => rom_read(rom, self.rom_bank as usize, addr),
+            /* Nothing else is ever on the other end of the link, so the
+             * sensor never sees light */
+            _ if self.ir_mode => 0xC0,
+            _ => ram_read(ram, self.ram_bank as usize, addr),
+        }
+    }
+
+    pub fn write8(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            /* There is no RAM enable, anything other than IR selects RAM */
+            0x0000 ..= 0x1FFF => self.ir_mode = value & 0x0F == 0x0E,
+            0x2000 ..= 0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000 ..= 0x5FFF => self.ram_bank = value & 0x03,
+            0x6000 ..= 0x7FFF => {},
+            /* LED on or off, there's nothing to send it to */
+            _ if self.ir_mode => {},
+            _ => ram_write(ram, self.ram_bank as usize, addr, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    #[test]
+    fn ir_replaces_ram() {
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = HuC1::new();
+
+        /* RAM is always enabled */
+        mbc.write8(&mut ram, 0xA000, 0x42);
+        assert_eq!(ram[0], 0x42);
+
+        mbc.write8(&mut ram, 0x0000, 0x0E);
+        assert_eq!(mbc.read8(&[], &ram, 0xA000), 0xC0);
+        mbc.write8(&mut ram, 0xA000, 0x01);
+        assert_eq!(ram[0], 0x42);
+
+        mbc.write8(&mut ram, 0x0000, 0x0A);
+        assert_eq!(mbc.read8(&[], &ram, 0xA000), 0x42);
+    }
+
+    #[test]
+    fn banking() {
+        let mut rom = vec![0; 64 * ROM_BANK_SIZE];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = HuC1::new();
+
+        assert_eq!(mbc.read8(&rom, &ram, 0x4000), 1);
+        mbc.write8(&mut ram, 0x2000, 0xFF);
+        assert_eq!(mbc.read8(&rom, &ram, 0x4000), 63);
+        assert_eq!(mbc.read8(&rom, &ram, 0x0000), 0);
+
+        mbc.write8(&mut ram, 0x4000, 0x02);
+        mbc.write8(&mut ram, 0xA001, 0x55);
+        assert_eq!(ram[2 * RAM_BANK_SIZE + 1], 0x55);
+    }
+}
@@ -0,0 +1,270 @@
+use log::debug;
+
+use super::{ram_read, ram_write, rom_read};
+use super::rtc::Clock;
+
+/* HuC3, banking plus an RTC, IR port and a piezo tone generator
+ * https://gbdev.io/pandocs/HuC3.html
+ *
+ * The RTC is a small microcontroller with 256 nibbles of memory that is
+ * driven by commands written to 0xA000.  Time is kept as minutes into the
+ * day and a day count, 12 bits each.
+ */
+const MODE_RAM_READ: u8  = 0x0;
+const MODE_RAM: u8       = 0xA;
+const MODE_COMMAND: u8   = 0xB;
+const MODE_RESPONSE: u8  = 0xC;
+const MODE_SEMAPHORE: u8 = 0xD;
+const MODE_IR: u8        = 0xE;
+
+const CMD_READ: u8      = 0x1;
+const CMD_WRITE: u8     = 0x3;
+const CMD_ADDR_LOW: u8  = 0x4;
+const CMD_ADDR_HIGH: u8 = 0x5;
+const CMD_EXTENDED: u8  = 0x6;
+
+const MINUTES_PER_DAY: u64 = 1440;
+
+const SAVE_SIZE: usize = 0x10C;
+
+pub struct HuC3 {
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    /* Last command written, run when the semaphore is released */
+    command: u8,
+    response: u8,
+    rtc_addr: u8,
+    rtc_mem: Vec<u8>,
+    clock: Box<dyn Clock>,
+    minutes: u16,
+    days: u16,
+    last_update: u64,
+}
+
+impl HuC3 {
+    pub fn new(clock: Box<dyn Clock>) -> HuC3 {
+        let last_update = clock.now();
+        HuC3 {
+            mode: MODE_RAM_READ,
+            rom_bank: 1,
+            ram_bank: 0,
+            command: 0,
+            response: 0,
+            rtc_addr: 0,
+            rtc_mem: vec![0; 0x100],
+            clock,
+            minutes: 0,
+            days: 0,
+            last_update,
+        }
+    }
+
+    /* Saved after the RAM: the RTC memory, one nibble per byte, then the
+     * minutes, days and the time they were saved at, little endian */
+    pub fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.rtc_mem);
+        out.extend_from_slice(&self.minutes.to_le_bytes());
+        out.extend_from_slice(&self.days.to_le_bytes());
+        out.extend_from_slice(&self.last_update.to_le_bytes());
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < SAVE_SIZE {
+            return;
+        }
+        self.rtc_mem.copy_from_slice(&data[..0x100]);
+        self.minutes = u16::from_le_bytes([data[0x100], data[0x101]]) % MINUTES_PER_DAY as u16;
+        self.days = u16::from_le_bytes([data[0x102], data[0x103]]) & 0xFFF;
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&data[0x104..0x10C]);
+        self.last_update = u64::from_le_bytes(timestamp);
+    }
+
+    pub fn read8(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom_read(rom, 0, addr),
+            0x4000 ..= 0x7FFF => rom_read(rom, self.rom_bank as usize, addr),
+            _ => match self.mode {
+                MODE_RAM_READ | MODE_RAM => ram_read(ram, self.ram_bank as usize, addr),
+                /* Echoes the command alongside the result nibble */
+                MODE_RESPONSE => 0x80 | (self.command & 0x70) | self.response,
+                /* Always ready */
+                MODE_SEMAPHORE => 0x01,
+                /* No light on the sensor */
+                MODE_IR => 0xC0,
+                _ => 0xFF,
+            },
+        }
+    }
+
+    pub fn write8(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.mode = value & 0x0F,
+            0x2000 ..= 0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000 ..= 0x5FFF => self.ram_bank = value & 0x03,
+            0x6000 ..= 0x7FFF => {},
+            _ => match self.mode {
+                MODE_RAM => ram_write(ram, self.ram_bank as usize, addr, value),
+                MODE_COMMAND => self.command = value,
+                /* Clearing bit 0 hands the command over to the RTC */
+                MODE_SEMAPHORE if value & 0x01 == 0 => self.run_command(),
+                /* LED on or off, there's nothing to send it to */
+                MODE_IR => {},
+                _ => {},
+            },
+        }
+    }
+
+    fn run_command(&mut self) {
+        let arg = self.command & 0x0F;
+        match (self.command >> 4) & 0x07 {
+            CMD_READ => {
+                self.response = self.rtc_mem[self.rtc_addr as usize];
+                self.rtc_addr = self.rtc_addr.wrapping_add(1);
+            },
+            CMD_WRITE => {
+                self.rtc_mem[self.rtc_addr as usize] = arg;
+                self.rtc_addr = self.rtc_addr.wrapping_add(1);
+            },
+            CMD_ADDR_LOW => self.rtc_addr = (self.rtc_addr & 0xF0) | arg,
+            CMD_ADDR_HIGH => self.rtc_addr = (self.rtc_addr & 0x0F) | (arg << 4),
+            CMD_EXTENDED => match arg {
+                /* Copy the time into memory 0x00-0x05 */
+                0x0 => {
+                    self.update();
+                    self.store_nibbles(0x00, self.minutes);
+                    self.store_nibbles(0x03, self.days);
+                },
+                /* And back out again to set it */
+                0x1 => {
+                    self.update();
+                    self.minutes = self.load_nibbles(0x00) % MINUTES_PER_DAY as u16;
+                    self.days = self.load_nibbles(0x03);
+                },
+                0x2 => self.response = 0x1,
+                /* There's no audio output to play it on */
+                0xE => debug!("HuC3 tone generator triggered"),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    /* Count whole minutes, keeping the leftover seconds for next time */
+    fn update(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_update) / 60;
+        self.last_update += elapsed * 60;
+
+        let minutes = self.minutes as u64 + elapsed;
+        self.minutes = (minutes % MINUTES_PER_DAY) as u16;
+        self.days = ((self.days as u64 + minutes / MINUTES_PER_DAY) & 0xFFF) as u16;
+    }
+
+    /* 12-bit values, least significant nibble first */
+    fn store_nibbles(&mut self, addr: usize, value: u16) {
+        for i in 0..3 {
+            self.rtc_mem[addr + i] = (value >> (i * 4)) as u8 & 0x0F;
+        }
+    }
+
+    fn load_nibbles(&self, addr: usize) -> u16 {
+        (0..3).fold(0, |value, i| value | ((self.rtc_mem[addr + i] & 0x0F) as u16) << (i * 4))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::rtc::FakeClock;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn huc3(start: u64) -> (HuC3, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(start));
+        (HuC3::new(Box::new(FakeClock(time.clone()))), time)
+    }
+
+    /* Write a command, release the semaphore and read the response */
+    fn command(mbc: &mut HuC3, command: u8) -> u8 {
+        mbc.write8(&mut [], 0x0000, MODE_COMMAND);
+        mbc.write8(&mut [], 0xA000, command);
+        mbc.write8(&mut [], 0x0000, MODE_SEMAPHORE);
+        assert_eq!(mbc.read8(&[], &[], 0xA000), 0x01);
+        mbc.write8(&mut [], 0xA000, 0x00);
+        mbc.write8(&mut [], 0x0000, MODE_RESPONSE);
+        mbc.read8(&[], &[], 0xA000)
+    }
+
+    fn set_addr(mbc: &mut HuC3, addr: u8) {
+        command(mbc, CMD_ADDR_LOW << 4 | (addr & 0x0F));
+        command(mbc, CMD_ADDR_HIGH << 4 | addr >> 4);
+    }
+
+    fn write_nibbles(mbc: &mut HuC3, addr: u8, value: u16) {
+        set_addr(mbc, addr);
+        for i in 0..3 {
+            command(mbc, CMD_WRITE << 4 | (value >> (i * 4)) as u8 & 0x0F);
+        }
+    }
+
+    fn read_nibbles(mbc: &mut HuC3, addr: u8) -> u16 {
+        set_addr(mbc, addr);
+        (0..3).fold(0, |value, i| value | ((command(mbc, CMD_READ << 4) & 0x0F) as u16) << (i * 4))
+    }
+
+    #[test]
+    fn memory_commands() {
+        let (mut mbc, _) = huc3(0);
+        set_addr(&mut mbc, 0x10);
+        command(&mut mbc, CMD_WRITE << 4 | 0x7);
+        command(&mut mbc, CMD_WRITE << 4 | 0x9);
+        assert_eq!(&mbc.rtc_mem[0x10..0x12], &[0x7, 0x9]);
+
+        /* The response echoes the command */
+        set_addr(&mut mbc, 0x10);
+        assert_eq!(command(&mut mbc, CMD_READ << 4), 0x80 | 0x10 | 0x7);
+        assert_eq!(command(&mut mbc, CMD_READ << 4), 0x80 | 0x10 | 0x9);
+
+        assert_eq!(command(&mut mbc, CMD_EXTENDED << 4 | 0x2) & 0x0F, 0x1);
+    }
+
+    #[test]
+    fn clock() {
+        let (mut mbc, time) = huc3(1000);
+
+        /* 12:34 on day 0x123 */
+        write_nibbles(&mut mbc, 0x00, 754);
+        write_nibbles(&mut mbc, 0x03, 0x123);
+        command(&mut mbc, CMD_EXTENDED << 4 | 0x1);
+
+        time.set(time.get() + 86400 + 10 * 60 + 30);
+        command(&mut mbc, CMD_EXTENDED << 4);
+        assert_eq!(read_nibbles(&mut mbc, 0x00), 764);
+        assert_eq!(read_nibbles(&mut mbc, 0x03), 0x124);
+
+        /* The leftover 30 seconds were kept */
+        time.set(time.get() + 30);
+        command(&mut mbc, CMD_EXTENDED << 4);
+        assert_eq!(read_nibbles(&mut mbc, 0x00), 765);
+    }
+
+    #[test]
+    fn ram_modes() {
+        let mut ram = vec![0; 0x2000];
+        let (mut mbc, _) = huc3(0);
+
+        mbc.write8(&mut ram, 0x0000, MODE_RAM);
+        mbc.write8(&mut ram, 0xA000, 0x42);
+        assert_eq!(ram[0], 0x42);
+
+        /* Mode 0 is read only */
+        mbc.write8(&mut ram, 0x0000, MODE_RAM_READ);
+        mbc.write8(&mut ram, 0xA000, 0x01);
+        assert_eq!(mbc.read8(&[], &ram, 0xA000), 0x42);
+
+        mbc.write8(&mut ram, 0x0000, MODE_IR);
+        assert_eq!(mbc.read8(&[], &ram, 0xA000), 0xC0);
+    }
+}
@@ -0,0 +1,169 @@
+use super::{ram_read, ram_write, rom_read, ROM_BANK_SIZE};
+
+/* MBC1, up to 2 MiB ROM and 32 KiB RAM
+ * https://gbdev.io/pandocs/MBC1.html
+ */
+pub struct Mbc1 {
+    ram_enabled: bool,
+    /* 5-bit ROM bank register, 0 is bumped to 1 */
+    bank1: u8,
+    /* 2-bit register, upper ROM bank bits or the RAM bank */
+    bank2: u8,
+    /* In mode 1 bank2 also applies to 0x0000-0x3FFF and to RAM */
+    advanced_mode: bool,
+    /* MBC1M multicarts only wire up 4 bits of bank1 */
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: &[u8]) -> Mbc1 {
+        Mbc1 {
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_mode: false,
+            multicart: is_multicart(rom),
+        }
+    }
+
+    pub fn read8(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => {
+                let bank = if self.advanced_mode { self.upper_bank() } else { 0 };
+                rom_read(rom, bank, addr)
+            },
+            0x4000 ..= 0x7FFF => {
+                let lower = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+                rom_read(rom, self.upper_bank() | lower as usize, addr)
+            },
+            _ => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                ram_read(ram, self.ram_bank(), addr)
+            },
+        }
+    }
+
+    pub fn write8(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            /* The zero check looks at all 5 bits, even on a multicart */
+            0x2000 ..= 0x3FFF => self.bank1 = match value & 0x1F { 0 => 1, bank => bank },
+            0x4000 ..= 0x5FFF => self.bank2 = value & 0x03,
+            0x6000 ..= 0x7FFF => self.advanced_mode = value & 0x01 != 0,
+            _ => {
+                if self.ram_enabled {
+                    ram_write(ram, self.ram_bank(), addr, value);
+                }
+            },
+        }
+    }
+
+    fn upper_bank(&self) -> usize {
+        let shift = if self.multicart { 4 } else { 5 };
+        (self.bank2 as usize) << shift
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_mode { self.bank2 as usize } else { 0 }
+    }
+}
+
+/* There is nothing in the header to tell an MBC1M apart.  They are all
+ * 1 MiB and each game has its own header, so look for a second copy of
+ * the Nintendo logo at the start of bank 0x10. */
+fn is_multicart(rom: &[u8]) -> bool {
+    const LOGO: std::ops::Range<usize> = 0x104..0x134;
+    let second = 0x10 * ROM_BANK_SIZE;
+
+    rom.len() == 0x100000 && rom[LOGO] == rom[second + LOGO.start..second + LOGO.end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Every bank starts with its own number */
+    fn rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0; size];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn bank_0_maps_to_1() {
+        let rom = rom(0x200000);
+        let mut mbc = Mbc1::new(&rom);
+
+        mbc.write8(&mut [], 0x2000, 0x00);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 1);
+
+        /* Only the 5 bits that exist are checked for zero */
+        mbc.write8(&mut [], 0x2000, 0xE0);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 1);
+
+        /* The upper bits don't take part, so bank 0x20 can't be reached */
+        mbc.write8(&mut [], 0x4000, 0x01);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 0x21);
+
+        mbc.write8(&mut [], 0x2000, 0x05);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 0x25);
+    }
+
+    #[test]
+    fn mode_1_upper_bits() {
+        let rom = rom(0x200000);
+        let mut mbc = Mbc1::new(&rom);
+        let mut ram = vec![0; 0x8000];
+
+        mbc.write8(&mut ram, 0x0000, 0x0A);
+        mbc.write8(&mut ram, 0x4000, 0x02);
+
+        /* Mode 0, 0x0000 is always bank 0 and RAM always bank 0 */
+        assert_eq!(mbc.read8(&rom, &ram, 0x0000), 0x00);
+        mbc.write8(&mut ram, 0xA000, 0x11);
+        assert_eq!(ram[0], 0x11);
+
+        /* Mode 1, bank2 applies to both */
+        mbc.write8(&mut ram, 0x6000, 0x01);
+        assert_eq!(mbc.read8(&rom, &ram, 0x0000), 0x40);
+        assert_eq!(mbc.read8(&rom, &ram, 0x4000), 0x41);
+        mbc.write8(&mut ram, 0xA000, 0x22);
+        assert_eq!(ram[2 * 0x2000], 0x22);
+        assert_eq!(mbc.read8(&rom, &ram, 0xA000), 0x22);
+
+        mbc.write8(&mut ram, 0x0000, 0x00);
+        assert_eq!(mbc.read8(&rom, &ram, 0xA000), 0xFF);
+    }
+
+    #[test]
+    fn multicart() {
+        let mut rom = rom(0x100000);
+        for (i, byte) in rom[0x104..0x134].iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        let logo = rom[0x104..0x134].to_vec();
+        rom[0x10 * ROM_BANK_SIZE + 0x104..0x10 * ROM_BANK_SIZE + 0x134].copy_from_slice(&logo);
+
+        let mut mbc = Mbc1::new(&rom);
+        assert!(mbc.multicart);
+
+        /* bank2 sits on bits 4-5, and bit 4 of bank1 goes nowhere */
+        mbc.write8(&mut [], 0x4000, 0x01);
+        mbc.write8(&mut [], 0x2000, 0x12);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 0x12);
+
+        /* The zero check still sees bit 4 */
+        mbc.write8(&mut [], 0x2000, 0x10);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 0x10);
+
+        mbc.write8(&mut [], 0x6000, 0x01);
+        assert_eq!(mbc.read8(&rom, &[], 0x0000), 0x10);
+
+        rom[0x10 * ROM_BANK_SIZE + 0x104] ^= 0xFF;
+        assert!(!Mbc1::new(&rom).multicart);
+    }
+}
@@ -0,0 +1,108 @@
+use super::rom_read;
+
+/* MBC2, up to 256 KiB ROM with 512x4 bits of RAM built in
+ * https://gbdev.io/pandocs/MBC2.html
+ *
+ * The RAM is kept one nibble per byte in the low 4 bits, which is also
+ * the layout of the 512 byte save file.
+ */
+pub const RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    ram_enabled: bool,
+    /* 4-bit ROM bank register, 0 is bumped to 1 */
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new() -> Mbc2 {
+        Mbc2 {
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    pub fn read8(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom_read(rom, 0, addr),
+            0x4000 ..= 0x7FFF => rom_read(rom, self.rom_bank as usize, addr),
+            /* Only 9 address lines, so the RAM echoes through 0xA000-0xBFFF.
+             * The upper nibble is not driven and reads back set. */
+            _ => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                0xF0 | ram[addr as usize & (RAM_SIZE - 1)]
+            },
+        }
+    }
+
+    pub fn write8(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            /* Address bit 8 picks the register anywhere in 0x0000-0x3FFF */
+            0x0000 ..= 0x3FFF => {
+                if addr & 0x0100 == 0 {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                } else {
+                    self.rom_bank = match value & 0x0F { 0 => 1, bank => bank };
+                }
+            },
+            0x4000 ..= 0x7FFF => {},
+            _ => {
+                if self.ram_enabled {
+                    ram[addr as usize & (RAM_SIZE - 1)] = value & 0x0F;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ROM_BANK_SIZE;
+
+    #[test]
+    fn register_select_by_address_bit_8() {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        let mut ram = vec![0; RAM_SIZE];
+        let mut mbc = Mbc2::new();
+
+        /* Bit 8 set is the ROM bank, even at the bottom of the range */
+        mbc.write8(&mut ram, 0x0100, 0x05);
+        assert_eq!(mbc.read8(&rom, &ram, 0x4000), 5);
+        mbc.write8(&mut ram, 0x3FFF, 0xF0);
+        assert_eq!(mbc.read8(&rom, &ram, 0x4000), 1);
+        assert!(!mbc.ram_enabled);
+
+        /* Bit 8 clear is RAM enable, even at the top of the range */
+        mbc.write8(&mut ram, 0x3E00, 0x0A);
+        assert!(mbc.ram_enabled);
+        assert_eq!(mbc.read8(&rom, &ram, 0x4000), 1);
+        mbc.write8(&mut ram, 0x0000, 0x00);
+        assert!(!mbc.ram_enabled);
+    }
+
+    #[test]
+    fn nibble_ram() {
+        let mut ram = vec![0; RAM_SIZE];
+        let mut mbc = Mbc2::new();
+
+        mbc.write8(&mut ram, 0xA000, 0x5A);
+        assert_eq!(ram[0], 0x00);
+
+        mbc.write8(&mut ram, 0x0000, 0x0A);
+        mbc.write8(&mut ram, 0xA001, 0x5A);
+        assert_eq!(ram[1], 0x0A);
+        assert_eq!(mbc.read8(&[], &ram, 0xA001), 0xFA);
+
+        /* 512 nibbles echoed through the whole range */
+        assert_eq!(mbc.read8(&[], &ram, 0xA201), 0xFA);
+        assert_eq!(mbc.read8(&[], &ram, 0xBE01), 0xFA);
+        mbc.write8(&mut ram, 0xB1FF, 0x03);
+        assert_eq!(ram[0x1FF], 0x03);
+    }
+}
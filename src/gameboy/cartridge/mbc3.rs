@@ -0,0 +1,83 @@
+use super::{ram_read, ram_write, rom_read};
+use super::rtc::{Rtc, RTC_DH, RTC_S};
+
+/* MBC3, up to 2 MiB ROM, 32 KiB RAM and an optional real time clock
+ * https://gbdev.io/pandocs/MBC3.html
+ */
+pub struct Mbc3 {
+    ram_enabled: bool,
+    /* 7-bit ROM bank register, 0 is bumped to 1 */
+    rom_bank: u8,
+    /* 0x00-0x03 maps a RAM bank, 0x08-0x0C an RTC register */
+    ram_select: u8,
+    /* Last value written to the latch register, latching needs 0 then 1 */
+    latch_value: u8,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(rtc: Option<Rtc>) -> Mbc3 {
+        Mbc3 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch_value: 0xFF,
+            rtc,
+        }
+    }
+
+    pub fn save(&self, out: &mut Vec<u8>) {
+        if let Some(rtc) = &self.rtc {
+            rtc.save(out);
+        }
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load(data);
+        }
+    }
+
+    pub fn read8(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom_read(rom, 0, addr),
+            0x4000 ..= 0x7FFF => rom_read(rom, self.rom_bank as usize, addr),
+            _ => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                match (self.ram_select, &self.rtc) {
+                    (0x00 ..= 0x03, _) => ram_read(ram, self.ram_select as usize, addr),
+                    (RTC_S ..= RTC_DH, Some(rtc)) => rtc.read(self.ram_select),
+                    _ => 0xFF,
+                }
+            },
+        }
+    }
+
+    pub fn write8(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000 ..= 0x3FFF => self.rom_bank = match value & 0x7F { 0 => 1, bank => bank },
+            0x4000 ..= 0x5FFF => self.ram_select = value,
+            0x6000 ..= 0x7FFF => {
+                if self.latch_value == 0x00 && value == 0x01 {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.latch_value = value;
+            },
+            _ => {
+                if !self.ram_enabled {
+                    return;
+                }
+                match (self.ram_select, &mut self.rtc) {
+                    (0x00 ..= 0x03, _) => ram_write(ram, self.ram_select as usize, addr, value),
+                    (RTC_S ..= RTC_DH, Some(rtc)) => rtc.write(self.ram_select, value),
+                    _ => {},
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use super::{ram_read, ram_write, rom_read};
+
+/* MBC5, up to 8 MiB ROM and 128 KiB RAM
+ * https://gbdev.io/pandocs/MBC5.html
+ */
+pub struct Mbc5 {
+    ram_enabled: bool,
+    /* 9-bit ROM bank, bank 0 can be mapped at 0x4000-0x7FFF */
+    rom_bank: u16,
+    ram_bank: u8,
+    /* Rumble carts drive the motor from bit 3 of the RAM bank register */
+    has_rumble: bool,
+    motor: bool,
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            motor: false,
+        }
+    }
+
+    pub fn motor(&self) -> bool {
+        self.motor
+    }
+
+    pub fn read8(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom_read(rom, 0, addr),
+            0x4000 ..= 0x7FFF => rom_read(rom, self.rom_bank as usize, addr),
+            _ => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                ram_read(ram, self.ram_bank as usize, addr)
+            },
+        }
+    }
+
+    pub fn write8(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            /* Unlike MBC1, all 8 bits are compared */
+            0x0000 ..= 0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000 ..= 0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000 ..= 0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value & 0x01) as u16) << 8,
+            0x4000 ..= 0x5FFF => {
+                if self.has_rumble {
+                    self.motor = value & 0x08 != 0;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            },
+            0x6000 ..= 0x7FFF => {},
+            _ => {
+                if self.ram_enabled {
+                    ram_write(ram, self.ram_bank as usize, addr, value);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    /* Every bank starts with its own number, little endian */
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+            chunk[1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    fn bank_at_4000(mbc: &Mbc5, rom: &[u8]) -> u16 {
+        mbc.read8(rom, &[], 0x4000) as u16 | (mbc.read8(rom, &[], 0x4001) as u16) << 8
+    }
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let rom = rom(512);
+        let mut mbc = Mbc5::new(false);
+
+        /* Bank 0 is not remapped */
+        mbc.write8(&mut [], 0x2000, 0x00);
+        assert_eq!(bank_at_4000(&mbc, &rom), 0);
+
+        mbc.write8(&mut [], 0x2000, 0x34);
+        mbc.write8(&mut [], 0x3000, 0xFF);
+        assert_eq!(bank_at_4000(&mbc, &rom), 0x134);
+
+        /* Each half keeps the other */
+        mbc.write8(&mut [], 0x2FFF, 0x02);
+        assert_eq!(bank_at_4000(&mbc, &rom), 0x102);
+        mbc.write8(&mut [], 0x3000, 0x00);
+        assert_eq!(bank_at_4000(&mbc, &rom), 0x002);
+    }
+
+    #[test]
+    fn ram_enable_needs_exact_value() {
+        let mut ram = vec![0; 16 * RAM_BANK_SIZE];
+        let mut mbc = Mbc5::new(false);
+
+        mbc.write8(&mut ram, 0x0000, 0x1A);
+        assert_eq!(mbc.read8(&[], &ram, 0xA000), 0xFF);
+
+        mbc.write8(&mut ram, 0x0000, 0x0A);
+        mbc.write8(&mut ram, 0x4000, 0x0F);
+        mbc.write8(&mut ram, 0xA000, 0x42);
+        assert_eq!(ram[15 * RAM_BANK_SIZE], 0x42);
+        assert!(!mbc.motor());
+    }
+
+    #[test]
+    fn rumble_bit_is_masked() {
+        let mut ram = vec![0; 8 * RAM_BANK_SIZE];
+        let mut mbc = Mbc5::new(true);
+        mbc.write8(&mut ram, 0x0000, 0x0A);
+
+        mbc.write8(&mut ram, 0x4000, 0x0B);
+        assert!(mbc.motor());
+        mbc.write8(&mut ram, 0xA000, 0x42);
+        assert_eq!(ram[3 * RAM_BANK_SIZE], 0x42);
+
+        mbc.write8(&mut ram, 0x4000, 0x03);
+        assert!(!mbc.motor());
+        assert_eq!(mbc.read8(&[], &ram, 0xA000), 0x42);
+    }
+}
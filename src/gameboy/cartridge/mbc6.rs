@@ -0,0 +1,247 @@
+/* MBC6, two independently switched 8 KiB ROM or flash windows and two
+ * 4 KiB RAM windows.  Only used by Net de Get, which keeps downloads in a
+ * 1 MiB flash chip.
+ * https://gbdev.io/pandocs/MBC6.html
+ *
+ * The flash command set is simplified: the unlock cycles are recognised by
+ * their data alone, and only byte program, sector erase and chip erase are
+ * supported.
+ */
+const ROM_BANK_SIZE: usize = 0x2000;
+const RAM_BANK_SIZE: usize = 0x1000;
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum FlashState {
+    Idle,
+    Unlock1,  /* 0xAA seen */
+    Unlock2,  /* 0xAA 0x55 seen, waiting for a command */
+    Program,  /* Next write is programmed */
+    Erase,    /* 0x80 seen, waiting for a second unlock */
+    Erase1,
+    Erase2,
+}
+
+#[derive(Copy, Clone)]
+struct Window {
+    bank: u8,
+    flash: bool,
+}
+
+pub struct Mbc6 {
+    ram_enabled: bool,
+    ram_banks: [u8; 2],
+    windows: [Window; 2],
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash_state: FlashState,
+    flash: Vec<u8>,
+}
+
+impl Mbc6 {
+    pub fn new() -> Mbc6 {
+        Mbc6 {
+            ram_enabled: false,
+            ram_banks: [0; 2],
+            windows: [Window { bank: 0, flash: false }; 2],
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash_state: FlashState::Idle,
+            flash: vec![0xFF; FLASH_SIZE],
+        }
+    }
+
+    /* The flash is saved after the RAM */
+    pub fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.flash);
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(FLASH_SIZE);
+        self.flash[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn read8(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom[addr as usize],
+            0x4000 ..= 0x7FFF => {
+                let window = self.windows[(addr as usize - 0x4000) / ROM_BANK_SIZE];
+                let offset = window.bank as usize * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1));
+                if !window.flash {
+                    rom[offset % rom.len()]
+                } else if self.flash_enabled {
+                    self.flash[offset % FLASH_SIZE]
+                } else {
+                    0xFF
+                }
+            },
+            _ => {
+                if !self.ram_enabled || ram.is_empty() {
+                    return 0xFF;
+                }
+                ram[self.ram_offset(addr) % ram.len()]
+            },
+        }
+    }
+
+    pub fn write8(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            0x0000 ..= 0x03FF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0400 ..= 0x07FF => self.ram_banks[0] = value & 0x07,
+            0x0800 ..= 0x0BFF => self.ram_banks[1] = value & 0x07,
+            0x0C00 ..= 0x0FFF => self.flash_enabled = value & 0x01 != 0,
+            0x1000            => self.flash_write_enabled = value & 0x01 != 0,
+            0x2000 ..= 0x27FF => self.windows[0].bank = value & 0x7F,
+            0x2800 ..= 0x2FFF => self.windows[0].flash = value == 0x08,
+            0x3000 ..= 0x37FF => self.windows[1].bank = value & 0x7F,
+            0x3800 ..= 0x3FFF => self.windows[1].flash = value == 0x08,
+            0x4000 ..= 0x7FFF => {
+                let window = self.windows[(addr as usize - 0x4000) / ROM_BANK_SIZE];
+                if window.flash && self.flash_enabled && self.flash_write_enabled {
+                    let offset = window.bank as usize * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1));
+                    self.flash_write(offset % FLASH_SIZE, value);
+                }
+            },
+            0xA000 ..= 0xBFFF if self.ram_enabled && !ram.is_empty() => {
+                let len = ram.len();
+                ram[self.ram_offset(addr) % len] = value;
+            },
+            _ => {},
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = self.ram_banks[(addr as usize - 0xA000) / RAM_BANK_SIZE];
+        bank as usize * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))
+    }
+
+    fn flash_write(&mut self, offset: usize, value: u8) {
+        self.flash_state = match (self.flash_state, value) {
+            /* Programming can only clear bits */
+            (FlashState::Program, _) => {
+                self.flash[offset] &= value;
+                FlashState::Idle
+            },
+            (FlashState::Idle, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0xA0) => FlashState::Program,
+            (FlashState::Unlock2, 0x80) => FlashState::Erase,
+            (FlashState::Erase, 0xAA) => FlashState::Erase1,
+            (FlashState::Erase1, 0x55) => FlashState::Erase2,
+            (FlashState::Erase2, 0x30) => {
+                let sector = offset & !(FLASH_SECTOR_SIZE - 1);
+                self.flash[sector..sector + FLASH_SECTOR_SIZE].iter_mut().for_each(|b| *b = 0xFF);
+                FlashState::Idle
+            },
+            (FlashState::Erase2, 0x10) => {
+                self.flash.iter_mut().for_each(|b| *b = 0xFF);
+                FlashState::Idle
+            },
+            /* 0xF0 resets, as does anything unexpected */
+            _ => FlashState::Idle,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Every 8 KiB bank starts with its own number */
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        rom
+    }
+
+    /* Flash readable and writable, window 0 on flash bank */
+    fn flash(bank: u8) -> Mbc6 {
+        let mut mbc = Mbc6::new();
+        mbc.write8(&mut [], 0x0C00, 0x01);
+        mbc.write8(&mut [], 0x1000, 0x01);
+        mbc.write8(&mut [], 0x2000, bank);
+        mbc.write8(&mut [], 0x2800, 0x08);
+        mbc
+    }
+
+    fn program(mbc: &mut Mbc6, addr: u16, value: u8) {
+        for &byte in [0xAA, 0x55, 0xA0].iter() {
+            mbc.write8(&mut [], 0x4000, byte);
+        }
+        mbc.write8(&mut [], addr, value);
+    }
+
+    fn erase(mbc: &mut Mbc6, addr: u16, command: u8) {
+        for &byte in [0xAA, 0x55, 0x80, 0xAA, 0x55].iter() {
+            mbc.write8(&mut [], 0x4000, byte);
+        }
+        mbc.write8(&mut [], addr, command);
+    }
+
+    #[test]
+    fn half_banks() {
+        let rom = rom(64);
+        let mut mbc = Mbc6::new();
+        mbc.write8(&mut [], 0x2000, 5);
+        mbc.write8(&mut [], 0x3000, 9);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 5);
+        assert_eq!(mbc.read8(&rom, &[], 0x6000), 9);
+        /* The first 16 KiB is fixed */
+        assert_eq!(mbc.read8(&rom, &[], 0x2000), 1);
+
+        /* Flash switched in but not enabled reads open bus */
+        mbc.write8(&mut [], 0x3800, 0x08);
+        assert_eq!(mbc.read8(&rom, &[], 0x6000), 0xFF);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 5);
+    }
+
+    #[test]
+    fn ram_halves() {
+        let mut ram = vec![0; 8 * RAM_BANK_SIZE];
+        let mut mbc = Mbc6::new();
+        mbc.write8(&mut ram, 0x0000, 0x0A);
+        mbc.write8(&mut ram, 0x0400, 1);
+        mbc.write8(&mut ram, 0x0800, 3);
+
+        mbc.write8(&mut ram, 0xA000, 0x11);
+        mbc.write8(&mut ram, 0xB000, 0x33);
+        assert_eq!(ram[RAM_BANK_SIZE], 0x11);
+        assert_eq!(ram[3 * RAM_BANK_SIZE], 0x33);
+    }
+
+    #[test]
+    fn flash_program() {
+        let mut mbc = flash(2);
+        program(&mut mbc, 0x4123, 0x0F);
+        assert_eq!(mbc.flash[2 * ROM_BANK_SIZE + 0x123], 0x0F);
+        assert_eq!(mbc.read8(&[], &[], 0x4123), 0x0F);
+
+        /* Programming only clears bits */
+        program(&mut mbc, 0x4123, 0xF3);
+        assert_eq!(mbc.read8(&[], &[], 0x4123), 0x03);
+
+        /* Nor without the write enable */
+        mbc.write8(&mut [], 0x1000, 0x00);
+        program(&mut mbc, 0x4124, 0x00);
+        assert_eq!(mbc.read8(&[], &[], 0x4124), 0xFF);
+    }
+
+    #[test]
+    fn flash_erase() {
+        /* Banks 2 and 16 are in the first and second sectors */
+        let mut mbc = flash(16);
+        program(&mut mbc, 0x4000, 0x00);
+        mbc.write8(&mut [], 0x2000, 2);
+        program(&mut mbc, 0x4000, 0x00);
+
+        erase(&mut mbc, 0x4000, 0x30);
+        assert_eq!(mbc.read8(&[], &[], 0x4000), 0xFF);
+        assert_eq!(mbc.flash[16 * ROM_BANK_SIZE], 0x00);
+
+        erase(&mut mbc, 0x4000, 0x10);
+        assert!(mbc.flash.iter().all(|&b| b == 0xFF));
+    }
+}
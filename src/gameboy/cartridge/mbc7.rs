@@ -0,0 +1,325 @@
+use super::rom_read;
+
+/* MBC7, banking plus a two axis accelerometer and a 93LC56 serial EEPROM
+ * https://gbdev.io/pandocs/MBC7.html
+ *
+ * The EEPROM is 128 16-bit words, kept little endian in the cartridge RAM
+ * so it gets saved like any other.
+ */
+pub const EEPROM_SIZE: usize = 0x100;
+
+/* Accelerometer reading when level, and the change for 1g of tilt */
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_1G: f32 = 0x70 as f32;
+
+/* EEPROM port bits at 0xA080 */
+const EEPROM_DO: u8  = 1 << 0;
+const EEPROM_DI: u8  = 1 << 1;
+const EEPROM_CLK: u8 = 1 << 6;
+const EEPROM_CS: u8  = 1 << 7;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum EepromState {
+    /* Waiting for a start bit */
+    Idle,
+    /* Shifting in 2 opcode and 8 address bits */
+    Command { bits: u8, value: u16 },
+    /* Shifting in 16 data bits for WRITE or WRAL */
+    Data { command: u16, bits: u8, value: u16 },
+    /* Shifting out a dummy 0 then 16 data bits */
+    Read { bits: u8, value: u16 },
+}
+
+pub struct Mbc7 {
+    ram_enabled: [bool; 2],
+    rom_bank: u8,
+    /* Tilt set by the frontend, in g */
+    tilt: (f32, f32),
+    latched: (u16, u16),
+    /* Latching needs an erase first */
+    latch_armed: bool,
+    eeprom_port: u8,
+    eeprom_state: EepromState,
+    eeprom_write_enabled: bool,
+}
+
+impl Mbc7 {
+    pub fn new() -> Mbc7 {
+        Mbc7 {
+            ram_enabled: [false; 2],
+            rom_bank: 1,
+            tilt: (0.0, 0.0),
+            latched: (0x8000, 0x8000),
+            latch_armed: false,
+            eeprom_port: EEPROM_DO,
+            eeprom_state: EepromState::Idle,
+            eeprom_write_enabled: false,
+        }
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    pub fn read8(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom_read(rom, 0, addr),
+            0x4000 ..= 0x7FFF => rom_read(rom, self.rom_bank as usize, addr),
+            0xA000 ..= 0xAFFF if self.ram_enabled == [true; 2] => match (addr >> 4) & 0x0F {
+                0x2 => self.latched.0 as u8,
+                0x3 => (self.latched.0 >> 8) as u8,
+                0x4 => self.latched.1 as u8,
+                0x5 => (self.latched.1 >> 8) as u8,
+                0x6 => 0x00,
+                0x8 => self.eeprom_port,
+                _ => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write8(&mut self, eeprom: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.ram_enabled[0] = value == 0x0A,
+            0x2000 ..= 0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000 ..= 0x5FFF => self.ram_enabled[1] = value == 0x40,
+            0xA000 ..= 0xAFFF if self.ram_enabled == [true; 2] => match (addr >> 4) & 0x0F {
+                0x0 if value == 0x55 => {
+                    self.latched = (0x8000, 0x8000);
+                    self.latch_armed = true;
+                },
+                0x1 if value == 0xAA && self.latch_armed => {
+                    self.latched = (accel(self.tilt.0), accel(self.tilt.1));
+                    self.latch_armed = false;
+                },
+                0x8 => self.eeprom_write(eeprom, value),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    /* Bit-banged Microwire, everything happens on the rising clock edge */
+    fn eeprom_write(&mut self, eeprom: &mut [u8], value: u8) {
+        let old = self.eeprom_port;
+        self.eeprom_port = (value & (EEPROM_CS | EEPROM_CLK | EEPROM_DI)) | (old & EEPROM_DO);
+
+        if value & EEPROM_CS == 0 {
+            self.eeprom_state = EepromState::Idle;
+            self.eeprom_port |= EEPROM_DO;
+            return;
+        }
+        if old & EEPROM_CLK != 0 || value & EEPROM_CLK == 0 {
+            return;
+        }
+
+        let bit = (value & EEPROM_DI != 0) as u16;
+        self.eeprom_state = match self.eeprom_state {
+            EepromState::Idle if bit == 1 => EepromState::Command { bits: 0, value: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, value } => {
+                let value = (value << 1) | bit;
+                if bits + 1 < 10 {
+                    EepromState::Command { bits: bits + 1, value }
+                } else {
+                    self.eeprom_command(eeprom, value)
+                }
+            },
+            EepromState::Data { command, bits, value } => {
+                let value = (value << 1) | bit;
+                if bits + 1 < 16 {
+                    EepromState::Data { command, bits: bits + 1, value }
+                } else {
+                    self.eeprom_data(eeprom, command, value);
+                    EepromState::Idle
+                }
+            },
+            EepromState::Read { bits, value } => {
+                self.eeprom_port &= !EEPROM_DO;
+                if value & 0x8000 != 0 {
+                    self.eeprom_port |= EEPROM_DO;
+                }
+                if bits + 1 < 16 {
+                    EepromState::Read { bits: bits + 1, value: value << 1 }
+                } else {
+                    EepromState::Idle
+                }
+            },
+        };
+    }
+
+    /* command holds the 2 opcode bits above 8 address bits */
+    fn eeprom_command(&mut self, eeprom: &mut [u8], command: u16) -> EepromState {
+        let addr = (command & 0x7F) as usize;
+        match (command >> 8, (command >> 6) & 0x03) {
+            /* READ, DO drops for the dummy bit first */
+            (0b10, _) => {
+                self.eeprom_port &= !EEPROM_DO;
+                EepromState::Read { bits: 0, value: read_word(eeprom, addr) }
+            },
+            (0b01, _) | (0b00, 0b01) => EepromState::Data { command, bits: 0, value: 0 },
+            /* ERASE */
+            (0b11, _) => {
+                if self.eeprom_write_enabled {
+                    write_word(eeprom, addr, 0xFFFF);
+                }
+                self.eeprom_port |= EEPROM_DO;
+                EepromState::Idle
+            },
+            /* ERAL */
+            (0b00, 0b10) => {
+                if self.eeprom_write_enabled {
+                    eeprom.iter_mut().for_each(|b| *b = 0xFF);
+                }
+                self.eeprom_port |= EEPROM_DO;
+                EepromState::Idle
+            },
+            /* EWEN and EWDS */
+            (0b00, 0b11) => {
+                self.eeprom_write_enabled = true;
+                EepromState::Idle
+            },
+            _ => {
+                self.eeprom_write_enabled = false;
+                EepromState::Idle
+            },
+        }
+    }
+
+    /* WRITE or WRAL once their data is in, DO high means ready */
+    fn eeprom_data(&mut self, eeprom: &mut [u8], command: u16, value: u16) {
+        if self.eeprom_write_enabled {
+            if command >> 8 == 0b01 {
+                write_word(eeprom, (command & 0x7F) as usize, value);
+            } else {
+                for addr in 0..EEPROM_SIZE / 2 {
+                    write_word(eeprom, addr, value);
+                }
+            }
+        }
+        self.eeprom_port |= EEPROM_DO;
+    }
+}
+
+fn accel(g: f32) -> u16 {
+    (ACCEL_CENTER + g.max(-4.0).min(4.0) * ACCEL_1G) as u16
+}
+
+fn read_word(eeprom: &[u8], addr: usize) -> u16 {
+    eeprom[addr * 2] as u16 | (eeprom[addr * 2 + 1] as u16) << 8
+}
+
+fn write_word(eeprom: &mut [u8], addr: usize, value: u16) {
+    eeprom[addr * 2] = value as u8;
+    eeprom[addr * 2 + 1] = (value >> 8) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORT: u16 = 0xA080;
+
+    fn enabled() -> Mbc7 {
+        let mut mbc = Mbc7::new();
+        mbc.write8(&mut [], 0x0000, 0x0A);
+        mbc.write8(&mut [], 0x4000, 0x40);
+        mbc
+    }
+
+    fn data_out(mbc: &Mbc7) -> bool {
+        mbc.read8(&[], PORT) & EEPROM_DO != 0
+    }
+
+    /* Clock one bit in with CS held high, returns DO after the edge */
+    fn clock(mbc: &mut Mbc7, eeprom: &mut [u8], bit: bool) -> bool {
+        let di = if bit { EEPROM_DI } else { 0 };
+        mbc.write8(eeprom, PORT, EEPROM_CS | di);
+        mbc.write8(eeprom, PORT, EEPROM_CS | EEPROM_CLK | di);
+        data_out(mbc)
+    }
+
+    /* Start bit then the low count bits of value, MSB first */
+    fn send(mbc: &mut Mbc7, eeprom: &mut [u8], value: u32, count: u32) {
+        clock(mbc, eeprom, true);
+        shift_in(mbc, eeprom, value, count);
+    }
+
+    fn shift_in(mbc: &mut Mbc7, eeprom: &mut [u8], value: u32, count: u32) {
+        for i in (0..count).rev() {
+            clock(mbc, eeprom, value >> i & 1 != 0);
+        }
+    }
+
+    fn deselect(mbc: &mut Mbc7, eeprom: &mut [u8]) {
+        mbc.write8(eeprom, PORT, 0);
+    }
+
+    fn read(mbc: &mut Mbc7, eeprom: &mut [u8], addr: u32) -> u16 {
+        send(mbc, eeprom, 0b10 << 8 | addr, 10);
+        /* Dummy 0 bit before the data */
+        assert!(!data_out(mbc));
+        let mut value = 0;
+        for _ in 0..16 {
+            value = value << 1 | clock(mbc, eeprom, false) as u16;
+        }
+        deselect(mbc, eeprom);
+        value
+    }
+
+    fn write(mbc: &mut Mbc7, eeprom: &mut [u8], addr: u32, value: u16) {
+        send(mbc, eeprom, 0b01 << 8 | addr, 10);
+        shift_in(mbc, eeprom, value as u32, 16);
+        assert!(data_out(mbc));
+        deselect(mbc, eeprom);
+    }
+
+    fn ewen(mbc: &mut Mbc7, eeprom: &mut [u8]) {
+        send(mbc, eeprom, 0b00_11 << 6, 10);
+        deselect(mbc, eeprom);
+    }
+
+    #[test]
+    fn write_needs_ewen() {
+        let mut eeprom = vec![0; EEPROM_SIZE];
+        let mut mbc = enabled();
+
+        write(&mut mbc, &mut eeprom, 0x05, 0x1234);
+        assert_eq!(read(&mut mbc, &mut eeprom, 0x05), 0x0000);
+
+        ewen(&mut mbc, &mut eeprom);
+        write(&mut mbc, &mut eeprom, 0x05, 0x1234);
+        assert_eq!(&eeprom[0x0A..0x0C], &[0x34, 0x12]);
+        assert_eq!(read(&mut mbc, &mut eeprom, 0x05), 0x1234);
+        assert_eq!(read(&mut mbc, &mut eeprom, 0x7F), 0x0000);
+    }
+
+    #[test]
+    fn eral_and_ready() {
+        let mut eeprom = vec![0; EEPROM_SIZE];
+        let mut mbc = enabled();
+        ewen(&mut mbc, &mut eeprom);
+
+        /* DO is low while a read is shifting out zeros, then ERAL sets
+         * it back to ready */
+        send(&mut mbc, &mut eeprom, 0b10 << 8, 10);
+        assert!(!clock(&mut mbc, &mut eeprom, false));
+        deselect(&mut mbc, &mut eeprom);
+        assert!(data_out(&mbc));
+
+        send(&mut mbc, &mut eeprom, 0b00_10 << 6, 10);
+        assert!(data_out(&mbc));
+        deselect(&mut mbc, &mut eeprom);
+        assert!(eeprom.iter().all(|&b| b == 0xFF));
+        assert_eq!(read(&mut mbc, &mut eeprom, 0x40), 0xFFFF);
+    }
+
+    #[test]
+    fn port_needs_both_enables() {
+        let mut mbc = Mbc7::new();
+        mbc.write8(&mut [], 0x0000, 0x0A);
+        assert_eq!(mbc.read8(&[], PORT), 0xFF);
+        mbc.write8(&mut [], 0x4000, 0x40);
+        assert_eq!(mbc.read8(&[], PORT), EEPROM_DO);
+    }
+}
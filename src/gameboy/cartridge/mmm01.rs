@@ -0,0 +1,179 @@
+use super::{ram_read, ram_write, rom_read};
+
+/* MMM01, the multicart mapper.  It starts up unmapped with the last 32 KiB
+ * of ROM (the menu) visible, the menu sets up the outer bank bits and masks
+ * for the chosen game and then locks them in.  From there it behaves much
+ * like an MBC1 confined to that game's slice of the ROM.
+ * https://gbdev.io/pandocs/MMM01.html
+ */
+pub struct Mmm01 {
+    mapped: bool,
+    ram_enabled: bool,
+    /* ROM bank bits 0-4, 5-6 and 7-8 */
+    rom_low: u8,
+    rom_mid: u8,
+    rom_high: u8,
+    /* RAM bank bits 0-1 and 2-3 */
+    ram_low: u8,
+    ram_high: u8,
+    /* Set bits of rom_low (1-4) and ram_low (0-1) are fixed once mapped */
+    rom_mask: u8,
+    ram_mask: u8,
+    mbc1_mode: bool,
+    mode_locked: bool,
+    /* Swaps rom_mid and ram_low, for games with more ROM than RAM banks */
+    multiplex: bool,
+}
+
+impl Mmm01 {
+    pub fn new() -> Mmm01 {
+        Mmm01 {
+            mapped: false,
+            ram_enabled: false,
+            rom_low: 0,
+            rom_mid: 0,
+            rom_high: 0,
+            ram_low: 0,
+            ram_high: 0,
+            rom_mask: 0,
+            ram_mask: 0,
+            mbc1_mode: false,
+            mode_locked: false,
+            multiplex: false,
+        }
+    }
+
+    pub fn read8(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        let last_bank = rom.len() / 0x4000 - 1;
+        match addr {
+            0x0000 ..= 0x3FFF if !self.mapped => rom_read(rom, last_bank - 1, addr),
+            0x4000 ..= 0x7FFF if !self.mapped => rom_read(rom, last_bank, addr),
+            /* The bits of rom_low the game controls read as 0 here */
+            0x0000 ..= 0x3FFF => rom_read(rom, self.rom_bank(self.rom_low & self.rom_mask), addr),
+            0x4000 ..= 0x7FFF => {
+                let mut low = self.rom_low;
+                if low & !self.rom_mask & 0x1F == 0 {
+                    low |= 1;
+                }
+                rom_read(rom, self.rom_bank(low), addr)
+            },
+            _ => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                ram_read(ram, self.ram_bank(), addr)
+            },
+        }
+    }
+
+    pub fn write8(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_mask = (value >> 4) & 0x03;
+                    self.mapped = value & 0x40 != 0;
+                }
+            },
+            0x2000 ..= 0x3FFF => {
+                self.rom_low = (self.rom_low & self.rom_mask) | (value & 0x1F & !self.rom_mask);
+                if !self.mapped {
+                    self.rom_low = value & 0x1F;
+                    self.rom_mid = (value >> 5) & 0x03;
+                }
+            },
+            0x4000 ..= 0x5FFF => {
+                self.ram_low = (self.ram_low & self.ram_mask) | (value & 0x03 & !self.ram_mask);
+                if !self.mapped {
+                    self.ram_low = value & 0x03;
+                    self.ram_high = (value >> 2) & 0x03;
+                    self.rom_high = (value >> 4) & 0x03;
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            },
+            0x6000 ..= 0x7FFF => {
+                if !self.mode_locked {
+                    self.mbc1_mode = value & 0x01 != 0;
+                }
+                if !self.mapped {
+                    self.rom_mask = ((value >> 2) & 0x0F) << 1;
+                    self.multiplex = value & 0x40 != 0;
+                }
+            },
+            _ => {
+                if self.ram_enabled {
+                    ram_write(ram, self.ram_bank(), addr, value);
+                }
+            },
+        }
+    }
+
+    fn rom_bank(&self, low: u8) -> usize {
+        let mid = if self.multiplex { self.ram_low } else { self.rom_mid };
+        (self.rom_high as usize) << 7 | (mid as usize) << 5 | low as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        let mut low = if self.multiplex { self.rom_mid } else { self.ram_low };
+        if !self.mbc1_mode {
+            low &= self.ram_mask;
+        }
+        (self.ram_high as usize) << 2 | low as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ROM_BANK_SIZE;
+
+    /* Every bank starts with its own number */
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn unmapped_shows_the_menu() {
+        let rom = rom(64);
+        let mut mbc = Mmm01::new();
+
+        assert_eq!(mbc.read8(&rom, &[], 0x0000), 62);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 63);
+
+        /* Bank writes go to the registers but do not show until mapped */
+        mbc.write8(&mut [], 0x2000, 0x22);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 63);
+    }
+
+    #[test]
+    fn mapping_locks_the_outer_bits() {
+        let rom = rom(64);
+        let mut mbc = Mmm01::new();
+
+        /* A 4 bank game at bank 32: rom_mid 1, bits 2-4 of rom_low fixed */
+        mbc.write8(&mut [], 0x2000, 0x20);
+        mbc.write8(&mut [], 0x6000, 0x0E << 2);
+        mbc.write8(&mut [], 0x0000, 0x40);
+
+        assert_eq!(mbc.read8(&rom, &[], 0x0000), 32);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 33);
+
+        mbc.write8(&mut [], 0x2000, 0x03);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 35);
+
+        /* Fixed bits, rom_mid and the mask can no longer change */
+        mbc.write8(&mut [], 0x2000, 0x7E);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 34);
+        mbc.write8(&mut [], 0x6000, 0x00);
+        mbc.write8(&mut [], 0x2000, 0x04);
+        assert_eq!(mbc.read8(&rom, &[], 0x4000), 33);
+
+        /* Nor does it unmap */
+        mbc.write8(&mut [], 0x0000, 0x00);
+        assert_eq!(mbc.read8(&rom, &[], 0x0000), 32);
+    }
+}
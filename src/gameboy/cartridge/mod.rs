@@ -0,0 +1,679 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use log::warn;
+
+use super::EmulatorError;
+use huc1::HuC1;
+use huc3::HuC3;
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+use mbc6::Mbc6;
+use mbc7::Mbc7;
+use mmm01::Mmm01;
+use rtc::{Rtc, SystemClock};
+pub use rtc::Clock;
+
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod rtc;
+
+/* Cartridge header, everything up to and including the global checksum
+ * https://gbdev.io/pandocs/The_Cartridge_Header.html
+ */
+const HEADER_END: usize = 0x150;
+
+const TITLE: usize = 0x134;
+const MANUFACTURER: usize = 0x13F;
+const CGB_FLAG: usize = 0x143;
+const NEW_LICENSEE: usize = 0x144;
+const SGB_FLAG: usize = 0x146;
+const CARTRIDGE_TYPE: usize = 0x147;
+const ROM_SIZE: usize = 0x148;
+const RAM_SIZE: usize = 0x149;
+const OLD_LICENSEE: usize = 0x14B;
+const VERSION: usize = 0x14C;
+const HEADER_CHECKSUM: usize = 0x14D;
+const GLOBAL_CHECKSUM: usize = 0x14E;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+/* Memory controller on the cartridge */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    HuC1,
+    HuC3,
+    PocketCamera,
+    Tama5,
+}
+
+/* Decoded cartridge type byte */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    /* Runs on both, with colour on a CGB */
+    Enhanced,
+    Only,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    /* Two ASCII characters, used when the old code is 0x33 */
+    New(String),
+}
+
+/* Mapper registers, one variant per supported controller */
+enum Mbc {
+    None,
+    Mbc1(Mbc1),
+    Mbc2(Mbc2),
+    Mbc3(Mbc3),
+    Mbc5(Mbc5),
+    Mbc6(Mbc6),
+    Mbc7(Mbc7),
+    Mmm01(Mmm01),
+    HuC1(HuC1),
+    HuC3(HuC3),
+}
+
+pub struct Cartridge {
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub kind: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: Mbc,
+}
+
+impl CartridgeType {
+    fn from_code(code: u8) -> Option<CartridgeType> {
+        /* (mapper, ram, battery, timer, rumble) */
+        let (mapper, ram, battery, timer, rumble) = match code {
+            0x00 => (Mapper::RomOnly, false, false, false, false),
+            0x01 => (Mapper::Mbc1, false, false, false, false),
+            0x02 => (Mapper::Mbc1, true, false, false, false),
+            0x03 => (Mapper::Mbc1, true, true, false, false),
+            0x05 => (Mapper::Mbc2, false, false, false, false),
+            0x06 => (Mapper::Mbc2, false, true, false, false),
+            0x08 => (Mapper::RomOnly, true, false, false, false),
+            0x09 => (Mapper::RomOnly, true, true, false, false),
+            0x0B => (Mapper::Mmm01, false, false, false, false),
+            0x0C => (Mapper::Mmm01, true, false, false, false),
+            0x0D => (Mapper::Mmm01, true, true, false, false),
+            0x0F => (Mapper::Mbc3, false, true, true, false),
+            0x10 => (Mapper::Mbc3, true, true, true, false),
+            0x11 => (Mapper::Mbc3, false, false, false, false),
+            0x12 => (Mapper::Mbc3, true, false, false, false),
+            0x13 => (Mapper::Mbc3, true, true, false, false),
+            0x19 => (Mapper::Mbc5, false, false, false, false),
+            0x1A => (Mapper::Mbc5, true, false, false, false),
+            0x1B => (Mapper::Mbc5, true, true, false, false),
+            0x1C => (Mapper::Mbc5, false, false, false, true),
+            0x1D => (Mapper::Mbc5, true, false, false, true),
+            0x1E => (Mapper::Mbc5, true, true, false, true),
+            0x20 => (Mapper::Mbc6, true, true, false, false),
+            0x22 => (Mapper::Mbc7, true, true, false, true),
+            0xFC => (Mapper::PocketCamera, true, true, false, false),
+            0xFD => (Mapper::Tama5, true, true, false, false),
+            0xFE => (Mapper::HuC3, true, true, true, false),
+            0xFF => (Mapper::HuC1, true, true, false, false),
+            _ => return None,
+        };
+        Some(CartridgeType { code, mapper, ram, battery, timer, rumble })
+    }
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Result<Cartridge, EmulatorError> {
+        Cartridge::with_clock(rom, Box::new(SystemClock))
+    }
+
+    /* Same as new, with the cartridge RTC (if any) running off clock
+     * instead of the wall time */
+    pub fn with_clock(mut rom: Vec<u8>, clock: Box<dyn Clock>) -> Result<Cartridge, EmulatorError> {
+        if rom.len() < HEADER_END {
+            return Err(EmulatorError::TruncatedRom { expected: HEADER_END, actual: rom.len() });
+        }
+
+        /* MMM01 multicarts boot into a menu in the last 32 KiB, and that
+         * is where the header describing the whole cartridge lives */
+        let base = if rom.len() >= 0x10000 && is_mmm01(rom[rom.len() - 0x8000 + CARTRIDGE_TYPE]) {
+            rom.len() - 0x8000
+        } else {
+            0
+        };
+        let header = rom[base..base + HEADER_END].to_vec();
+
+        let checksum = header[TITLE..HEADER_CHECKSUM].iter()
+            .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+        if checksum != header[HEADER_CHECKSUM] {
+            return Err(EmulatorError::BadHeaderChecksum { expected: header[HEADER_CHECKSUM], actual: checksum });
+        }
+
+        let kind = CartridgeType::from_code(header[CARTRIDGE_TYPE])
+            .ok_or(EmulatorError::UnsupportedMapper(header[CARTRIDGE_TYPE]))?;
+
+        let rom_size = match header[ROM_SIZE] {
+            code @ 0..=8 => 0x8000 << code,
+            code => return Err(EmulatorError::InvalidRom(format!("unknown ROM size code {:#04X}", code))),
+        };
+        if rom.len() < rom_size {
+            return Err(EmulatorError::TruncatedRom { expected: rom_size, actual: rom.len() });
+        }
+        /* Anything past the declared size is an overdump */
+        rom.truncate(rom_size);
+
+        let ram_size = match header[RAM_SIZE] {
+            0 => 0,
+            1 => 0x800, /* Unofficial, listed by some homebrew */
+            2 => 0x2000,
+            3 => 0x8000,
+            4 => 0x20000,
+            5 => 0x10000,
+            code => return Err(EmulatorError::InvalidRom(format!("unknown RAM size code {:#04X}", code))),
+        };
+
+        let cgb = match header[CGB_FLAG] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        /* The title shrank over time, first to make room for the CGB flag
+         * and then for a 4 character manufacturer code */
+        let manufacturer = &header[MANUFACTURER..CGB_FLAG];
+        let (title, manufacturer) = if cgb == CgbSupport::None {
+            (header_string(&header[TITLE..NEW_LICENSEE]), None)
+        } else if manufacturer.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+            (header_string(&header[TITLE..MANUFACTURER]), Some(header_string(manufacturer)))
+        } else {
+            (header_string(&header[TITLE..CGB_FLAG]), None)
+        };
+
+        let licensee = match header[OLD_LICENSEE] {
+            0x33 => Licensee::New(header_string(&header[NEW_LICENSEE..SGB_FLAG])),
+            code => Licensee::Old(code),
+        };
+
+        let mbc = match kind.mapper {
+            Mapper::RomOnly => Mbc::None,
+            Mapper::Mbc1 => Mbc::Mbc1(Mbc1::new(&rom)),
+            Mapper::Mbc2 => Mbc::Mbc2(Mbc2::new()),
+            Mapper::Mbc3 => Mbc::Mbc3(Mbc3::new(if kind.timer { Some(Rtc::new(clock)) } else { None })),
+            Mapper::Mbc5 => Mbc::Mbc5(Mbc5::new(kind.rumble)),
+            Mapper::Mbc6 => Mbc::Mbc6(Mbc6::new()),
+            Mapper::Mbc7 => Mbc::Mbc7(Mbc7::new()),
+            Mapper::Mmm01 => Mbc::Mmm01(Mmm01::new()),
+            Mapper::HuC1 => Mbc::HuC1(HuC1::new()),
+            Mapper::HuC3 => Mbc::HuC3(HuC3::new(clock)),
+            _ => return Err(EmulatorError::UnsupportedMapper(kind.code)),
+        };
+
+        /* MBC2 RAM is inside the controller and MBC7 has an EEPROM, the
+         * header claims no RAM for either */
+        let ram_len = match kind.mapper {
+            Mapper::Mbc2 => mbc2::RAM_SIZE,
+            Mapper::Mbc7 => mbc7::EEPROM_SIZE,
+            _ if kind.ram => ram_size,
+            _ => 0,
+        };
+
+        let cart = Cartridge {
+            title,
+            manufacturer,
+            cgb,
+            sgb: header[SGB_FLAG] == 0x03,
+            kind,
+            rom_size,
+            ram_size,
+            licensee,
+            version: header[VERSION],
+            header_checksum: header[HEADER_CHECKSUM],
+            global_checksum: ((header[GLOBAL_CHECKSUM] as u16) << 8) | header[GLOBAL_CHECKSUM + 1] as u16,
+            ram: vec![0; ram_len],
+            rom,
+            mbc,
+        };
+
+        /* The boot ROM never checks this one, so plenty of homebrew gets it
+         * wrong.  Only worth a warning. */
+        if global_checksum(&cart.rom, base + GLOBAL_CHECKSUM) != cart.global_checksum {
+            warn!("Global checksum mismatch, header says {:#06X}", cart.global_checksum);
+        }
+
+        Ok(cart)
+    }
+
+    /* 0x0000-0x7FFF and 0xA000-0xBFFF */
+    pub fn read8(&self, addr: u16) -> u8 {
+        match &self.mbc {
+            Mbc::None => match addr {
+                0x0000 ..= 0x7FFF => self.rom[addr as usize],
+                _ => ram_read(&self.ram, 0, addr),
+            },
+            Mbc::Mbc1(mbc) => mbc.read8(&self.rom, &self.ram, addr),
+            Mbc::Mbc2(mbc) => mbc.read8(&self.rom, &self.ram, addr),
+            Mbc::Mbc3(mbc) => mbc.read8(&self.rom, &self.ram, addr),
+            Mbc::Mbc5(mbc) => mbc.read8(&self.rom, &self.ram, addr),
+            Mbc::Mbc6(mbc) => mbc.read8(&self.rom, &self.ram, addr),
+            Mbc::Mbc7(mbc) => mbc.read8(&self.rom, addr),
+            Mbc::Mmm01(mbc) => mbc.read8(&self.rom, &self.ram, addr),
+            Mbc::HuC1(mbc) => mbc.read8(&self.rom, &self.ram, addr),
+            Mbc::HuC3(mbc) => mbc.read8(&self.rom, &self.ram, addr),
+        }
+    }
+
+    pub fn write8(&mut self, addr: u16, value: u8) {
+        match &mut self.mbc {
+            Mbc::None => match addr {
+                0x0000 ..= 0x7FFF => {},
+                _ => ram_write(&mut self.ram, 0, addr, value),
+            },
+            Mbc::Mbc1(mbc) => mbc.write8(&mut self.ram, addr, value),
+            Mbc::Mbc2(mbc) => mbc.write8(&mut self.ram, addr, value),
+            Mbc::Mbc3(mbc) => mbc.write8(&mut self.ram, addr, value),
+            Mbc::Mbc5(mbc) => mbc.write8(&mut self.ram, addr, value),
+            Mbc::Mbc6(mbc) => mbc.write8(&mut self.ram, addr, value),
+            Mbc::Mbc7(mbc) => mbc.write8(&mut self.ram, addr, value),
+            Mbc::Mmm01(mbc) => mbc.write8(&mut self.ram, addr, value),
+            Mbc::HuC1(mbc) => mbc.write8(&mut self.ram, addr, value),
+            Mbc::HuC3(mbc) => mbc.write8(&mut self.ram, addr, value),
+        }
+    }
+
+    /* Rumble motor state, always off for carts without one */
+    pub fn rumble(&self) -> bool {
+        match &self.mbc {
+            Mbc::Mbc5(mbc) => mbc.motor(),
+            _ => false,
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.kind.battery
+    }
+
+    /* Battery backed state in .sav layout, the RAM followed by anything
+     * else the mapper keeps: the RTC, or MBC6 flash.  MBC2 RAM is saved one
+     * nibble per byte and MBC7 EEPROM is in the RAM. */
+    pub fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        match &self.mbc {
+            Mbc::Mbc3(mbc) => mbc.save(&mut data),
+            Mbc::Mbc6(mbc) => mbc.save(&mut data),
+            Mbc::HuC3(mbc) => mbc.save(&mut data),
+            _ => {},
+        }
+        data
+    }
+
+    /* Short saves are loaded as far as they go, the rest is left as is */
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let len = self.ram.len().min(data.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+
+        let extra = &data[len..];
+        match &mut self.mbc {
+            Mbc::Mbc2(_) => self.ram.iter_mut().for_each(|b| *b &= 0x0F),
+            Mbc::Mbc3(mbc) => mbc.load(extra),
+            Mbc::Mbc6(mbc) => mbc.load(extra),
+            Mbc::HuC3(mbc) => mbc.load(extra),
+            _ => {},
+        }
+    }
+
+    /* A missing save is not an error, the game starts with blank RAM */
+    pub fn load_save(&mut self, path: &Path) -> Result<(), EmulatorError> {
+        match fs::read(path) {
+            Ok(data) => {
+                self.load_save_data(&data);
+                Ok(())
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /* Written to a temporary file and renamed over the old save, so a crash
+     * part way through leaves the previous save intact */
+    pub fn write_save(&self, path: &Path) -> Result<(), EmulatorError> {
+        let tmp = path.with_extension("sav.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&self.save_data())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /* Accelerometer input in g, only MBC7 carts have one */
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        if let Mbc::Mbc7(mbc) = &mut self.mbc {
+            mbc.set_tilt(x, y);
+        }
+    }
+}
+
+/* Sum of every ROM byte except the checksum itself */
+fn global_checksum(rom: &[u8], checksum: usize) -> u16 {
+    rom.iter().enumerate()
+        .filter(|(i, _)| *i != checksum && *i != checksum + 1)
+        .fold(0u16, |sum, (_, b)| sum.wrapping_add(*b as u16))
+}
+
+fn is_mmm01(code: u8) -> bool {
+    CartridgeType::from_code(code).map_or(false, |kind| kind.mapper == Mapper::Mmm01)
+}
+
+/* ROM and RAM sizes are powers of two, so out of range banks wrap around
+ * the same as they would with the high address lines not connected */
+fn rom_read(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    rom[(bank * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1))) % rom.len()]
+}
+
+/* Open bus with no RAM fitted */
+fn ram_read(ram: &[u8], bank: usize, addr: u16) -> u8 {
+    if ram.is_empty() {
+        return 0xFF;
+    }
+    ram[(bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) % ram.len()]
+}
+
+fn ram_write(ram: &mut [u8], bank: usize, addr: u16, value: u8) {
+    if ram.is_empty() {
+        return;
+    }
+    let len = ram.len();
+    ram[(bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) % len] = value;
+}
+
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.mapper)?;
+        for (present, name) in [(self.ram, "RAM"), (self.battery, "BATTERY"),
+                                (self.timer, "TIMER"), (self.rumble, "RUMBLE")].iter() {
+            if *present {
+                write!(f, "+{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cartridge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self.title)?;
+        if let Some(manufacturer) = &self.manufacturer {
+            write!(f, " [{}]", manufacturer)?;
+        }
+        write!(f, " v{}, {} ({:#04X}), {} KiB ROM, {} KiB RAM, CGB: {:?}, SGB: {}, licensee: {:?}, header checksum {:#04X}, global checksum {:#06X}",
+               self.version, self.kind, self.kind.code, self.rom_size / 1024, self.ram_size / 1024,
+               self.cgb, self.sgb, self.licensee, self.header_checksum, self.global_checksum)
+    }
+}
+
+/* Header strings are NUL padded ASCII */
+fn header_string(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|b| **b != 0)
+        .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::rtc::FakeClock;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /* A 32 KiB ROM only cartridge, edited then given a valid header
+     * checksum */
+    fn rom_with<F: FnOnce(&mut Vec<u8>)>(edit: F) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        edit(&mut rom);
+        rom[HEADER_CHECKSUM] = rom[TITLE..HEADER_CHECKSUM].iter()
+            .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+        rom
+    }
+
+    fn error(rom: Vec<u8>) -> EmulatorError {
+        match Cartridge::new(rom) {
+            Ok(_) => panic!("header was accepted"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn header_checksum() {
+        let mut rom = rom_with(|rom| rom[TITLE] = b'A');
+        assert!(Cartridge::new(rom.clone()).is_ok());
+
+        rom[HEADER_CHECKSUM] ^= 0x01;
+        let expected = rom[HEADER_CHECKSUM];
+        assert!(matches!(error(rom), EmulatorError::BadHeaderChecksum { expected: e, actual: a }
+                         if e == expected && a == expected ^ 0x01));
+    }
+
+    #[test]
+    fn global_checksum_only_warns() {
+        let mut rom = rom_with(|rom| rom[GLOBAL_CHECKSUM] = 0x12);
+        let cart = Cartridge::new(rom.clone()).unwrap();
+        assert_ne!(global_checksum(&cart.rom, GLOBAL_CHECKSUM), cart.global_checksum);
+
+        /* Sum of everything but the checksum bytes */
+        let sum = rom.iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16)) - 0x12;
+        rom[GLOBAL_CHECKSUM] = (sum >> 8) as u8;
+        rom[GLOBAL_CHECKSUM + 1] = sum as u8;
+        let cart = Cartridge::new(rom).unwrap();
+        assert_eq!(global_checksum(&cart.rom, GLOBAL_CHECKSUM), cart.global_checksum);
+    }
+
+    #[test]
+    fn size_codes() {
+        let cart = Cartridge::new(rom_with(|rom| {
+            rom.resize(0x20000, 0);
+            rom[CARTRIDGE_TYPE] = 0x09;
+            rom[ROM_SIZE] = 0x02;
+            rom[RAM_SIZE] = 0x03;
+        })).unwrap();
+        assert_eq!(cart.rom_size, 0x20000);
+        assert_eq!(cart.ram_size, 0x8000);
+        assert_eq!(cart.ram.len(), 0x8000);
+        assert!(cart.kind.battery);
+
+        /* No RAM fitted whatever the size says */
+        let cart = Cartridge::new(rom_with(|rom| rom[RAM_SIZE] = 0x02)).unwrap();
+        assert_eq!(cart.ram_size, 0x2000);
+        assert!(cart.ram.is_empty());
+
+        assert!(matches!(error(rom_with(|rom| rom[ROM_SIZE] = 0x09)), EmulatorError::InvalidRom(_)));
+        assert!(matches!(error(rom_with(|rom| rom[RAM_SIZE] = 0x06)), EmulatorError::InvalidRom(_)));
+    }
+
+    #[test]
+    fn truncation() {
+        assert!(matches!(error(vec![0; 0x100]),
+                         EmulatorError::TruncatedRom { expected: HEADER_END, actual: 0x100 }));
+        assert!(matches!(error(rom_with(|rom| rom[ROM_SIZE] = 0x01)),
+                         EmulatorError::TruncatedRom { expected: 0x10000, actual: 0x8000 }));
+
+        let cart = Cartridge::new(rom_with(|rom| rom.resize(0x10000, 0xFF))).unwrap();
+        assert_eq!(cart.rom.len(), 0x8000);
+    }
+
+    #[test]
+    fn title_and_manufacturer() {
+        let set = |rom: &mut Vec<u8>, title: &[u8]| rom[TITLE..TITLE + title.len()].copy_from_slice(title);
+
+        let cart = Cartridge::new(rom_with(|rom| set(rom, b"TETRIS"))).unwrap();
+        assert_eq!(cart.title, "TETRIS");
+        assert_eq!(cart.manufacturer, None);
+        assert_eq!(cart.cgb, CgbSupport::None);
+
+        let cart = Cartridge::new(rom_with(|rom| {
+            set(rom, b"POKEMON    AAXE");
+            rom[CGB_FLAG] = 0x80;
+        })).unwrap();
+        assert_eq!(cart.title, "POKEMON");
+        assert_eq!(cart.manufacturer, Some("AAXE".to_string()));
+        assert_eq!(cart.cgb, CgbSupport::Enhanced);
+
+        /* Not a manufacturer code, so part of the title */
+        let cart = Cartridge::new(rom_with(|rom| {
+            set(rom, b"ABCDEFGHIJK LMN");
+            rom[CGB_FLAG] = 0xC0;
+        })).unwrap();
+        assert_eq!(cart.title, "ABCDEFGHIJK LMN");
+        assert_eq!(cart.manufacturer, None);
+        assert_eq!(cart.cgb, CgbSupport::Only);
+
+        let cart = Cartridge::new(rom_with(|rom| {
+            rom[OLD_LICENSEE] = 0x33;
+            rom[NEW_LICENSEE..SGB_FLAG].copy_from_slice(b"01");
+        })).unwrap();
+        assert_eq!(cart.licensee, Licensee::New("01".to_string()));
+    }
+
+    #[test]
+    fn unsupported_mapper() {
+        assert!(matches!(error(rom_with(|rom| rom[CARTRIDGE_TYPE] = 0x04)), EmulatorError::UnsupportedMapper(0x04)));
+        assert!(matches!(error(rom_with(|rom| rom[CARTRIDGE_TYPE] = 0xFD)), EmulatorError::UnsupportedMapper(0xFD)));
+    }
+
+    /* MBC1+RAM+BATTERY with 8 KiB of RAM, the RAM enabled */
+    fn battery_cart() -> Cartridge {
+        let mut cart = Cartridge::new(rom_with(|rom| {
+            rom[CARTRIDGE_TYPE] = 0x03;
+            rom[RAM_SIZE] = 0x02;
+        })).unwrap();
+        cart.write8(0x0000, 0x0A);
+        cart
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let mut cart = battery_cart();
+        cart.write8(0xA000, 0x12);
+        cart.write8(0xBFFF, 0x34);
+        let save = cart.save_data();
+        assert_eq!(save.len(), 0x2000);
+
+        let mut loaded = battery_cart();
+        loaded.load_save_data(&save);
+        assert_eq!(loaded.read8(0xA000), 0x12);
+        assert_eq!(loaded.read8(0xBFFF), 0x34);
+
+        /* A short save only fills the start of the RAM */
+        let mut loaded = battery_cart();
+        loaded.load_save_data(&[0x56]);
+        assert_eq!(loaded.read8(0xA000), 0x56);
+        assert_eq!(loaded.read8(0xA001), 0x00);
+    }
+
+    #[test]
+    fn rtc_footer() {
+        /* MBC3+TIMER+RAM+BATTERY with 8 KiB of RAM */
+        let mbc3 = |time: &Rc<Cell<u64>>| {
+            let rom = rom_with(|rom| {
+                rom[CARTRIDGE_TYPE] = 0x10;
+                rom[RAM_SIZE] = 0x02;
+            });
+            let mut cart = Cartridge::with_clock(rom, Box::new(FakeClock(time.clone()))).unwrap();
+            cart.write8(0x0000, 0x0A);
+            cart
+        };
+        let latch = |cart: &mut Cartridge| {
+            cart.write8(0x6000, 0x00);
+            cart.write8(0x6000, 0x01);
+        };
+
+        let time = Rc::new(Cell::new(1000));
+        let mut cart = mbc3(&time);
+        cart.write8(0xA000, 0x99);
+        time.set(1030);
+        let save = cart.save_data();
+        /* The RAM, then the 48 byte RTC footer */
+        assert_eq!(save.len(), 0x2000 + 48);
+        assert_eq!(save[0], 0x99);
+
+        /* The seconds register picks up the time spent switched off */
+        time.set(1040);
+        let mut loaded = mbc3(&time);
+        loaded.load_save_data(&save);
+        assert_eq!(loaded.read8(0xA000), 0x99);
+        latch(&mut loaded);
+        loaded.write8(0x4000, 0x08);
+        assert_eq!(loaded.read8(0xA000), 40);
+    }
+
+    #[test]
+    fn write_save_replaces_atomically() {
+        let dir = std::env::temp_dir().join(format!("rusty-gbe-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.sav");
+        fs::write(&path, b"old").unwrap();
+
+        let mut cart = battery_cart();
+        cart.write8(0xA000, 0x42);
+        cart.write_save(&path).unwrap();
+
+        /* The temporary file is renamed over the save, nothing is left
+         * beside it */
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!(data[0], 0x42);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut loaded = battery_cart();
+        loaded.load_save(&path).unwrap();
+        assert_eq!(loaded.read8(0xA000), 0x42);
+
+        /* A missing save leaves the RAM blank */
+        fs::remove_file(&path).unwrap();
+        let mut loaded = battery_cart();
+        loaded.load_save(&path).unwrap();
+        assert_eq!(loaded.read8(0xA000), 0x00);
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,339 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/* Time source for cartridge clocks, in whole seconds from any fixed point */
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+/* Wall time */
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+/* RTC register select values, as written to 0x4000-0x5FFF */
+pub const RTC_S: u8  = 0x08;
+pub const RTC_M: u8  = 0x09;
+pub const RTC_H: u8  = 0x0A;
+pub const RTC_DL: u8 = 0x0B;
+pub const RTC_DH: u8 = 0x0C;
+
+const RTC_SAVE_SIZE: usize = 48;
+
+const DH_DAY_MSB: u8 = 1 << 0;
+const DH_HALT: u8    = 1 << 6;
+const DH_CARRY: u8   = 1 << 7;
+
+#[derive(Copy, Clone, Default)]
+struct RtcRegs {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16, /* 9 bits */
+    halted: bool,
+    day_carry: bool,
+}
+
+/* MBC3 real time clock
+ *
+ * The counters only move forward when the cartridge is touched, by however
+ * many seconds the clock says have passed since the last time.  The CPU
+ * only ever reads the latched copy.
+ */
+pub struct Rtc {
+    clock: Box<dyn Clock>,
+    live: RtcRegs,
+    latched: RtcRegs,
+    last_update: u64,
+}
+
+impl RtcRegs {
+    fn read(&self, reg: u8) -> u8 {
+        match reg {
+            RTC_S => self.seconds,
+            RTC_M => self.minutes,
+            RTC_H => self.hours,
+            RTC_DL => self.days as u8,
+            _ => {
+                let mut dh = (self.days >> 8) as u8 & DH_DAY_MSB;
+                if self.halted {
+                    dh |= DH_HALT;
+                }
+                if self.day_carry {
+                    dh |= DH_CARRY;
+                }
+                dh
+            },
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        match reg {
+            RTC_S => self.seconds = value & 0x3F,
+            RTC_M => self.minutes = value & 0x3F,
+            RTC_H => self.hours = value & 0x1F,
+            RTC_DL => self.days = (self.days & 0x100) | value as u16,
+            _ => {
+                self.days = (self.days & 0xFF) | ((value & DH_DAY_MSB) as u16) << 8;
+                self.halted = value & DH_HALT != 0;
+                self.day_carry = value & DH_CARRY != 0;
+            },
+        }
+    }
+
+    fn advance(&mut self, mut secs: u64) {
+        /* Values written out of range count up to the limit of the register
+         * and wrap to 0 without carrying, so step those a second at a time */
+        while secs > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            secs -= 1;
+        }
+        if secs == 0 {
+            return;
+        }
+
+        let total = secs + self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+
+        let days = self.days as u64 + total / 86400;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days = (self.days + 1) & 0x1FF;
+        if self.days == 0 {
+            self.day_carry = true;
+        }
+    }
+}
+
+impl Rtc {
+    pub fn new(clock: Box<dyn Clock>) -> Rtc {
+        let last_update = clock.now();
+        Rtc {
+            clock,
+            live: RtcRegs::default(),
+            latched: RtcRegs::default(),
+            last_update,
+        }
+    }
+
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched.read(reg)
+    }
+
+    pub fn write(&mut self, reg: u8, value: u8) {
+        self.update();
+        self.live.write(reg, value);
+    }
+
+    pub fn latch(&mut self) {
+        self.update();
+        self.latched = self.live;
+    }
+
+    /* The 48 byte footer BGB and VBA-M append to MBC3 saves: the live
+     * then latched registers as 32-bit words, then the time they were
+     * saved at, all little endian */
+    pub fn save(&self, out: &mut Vec<u8>) {
+        for regs in [&self.live, &self.latched].iter() {
+            for reg in RTC_S..=RTC_DH {
+                out.extend_from_slice(&(regs.read(reg) as u32).to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&self.last_update.to_le_bytes());
+    }
+
+    /* The time spent switched off gets counted on the next update */
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < RTC_SAVE_SIZE {
+            return;
+        }
+        for (i, reg) in (RTC_S..=RTC_DH).enumerate() {
+            self.live.write(reg, data[i * 4]);
+            self.latched.write(reg, data[20 + i * 4]);
+        }
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&data[40..48]);
+        self.last_update = u64::from_le_bytes(timestamp);
+    }
+
+    /* Catch the counters up with the clock, unless halted */
+    fn update(&mut self) {
+        let now = self.clock.now();
+        if !self.live.halted {
+            self.live.advance(now.saturating_sub(self.last_update));
+        }
+        self.last_update = now;
+    }
+}
+
+#[cfg(test)]
+pub use self::tests::FakeClock;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mbc3::Mbc3;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /* A clock the test moves by hand */
+    pub struct FakeClock(pub Rc<Cell<u64>>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn fake_rtc(start: u64) -> (Rtc, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(start));
+        (Rtc::new(Box::new(FakeClock(time.clone()))), time)
+    }
+
+    fn advance(time: &Rc<Cell<u64>>, secs: u64) {
+        time.set(time.get() + secs);
+    }
+
+    #[test]
+    fn latch_on_0_then_1() {
+        let (rtc, time) = fake_rtc(1000);
+        let mut mbc = Mbc3::new(Some(rtc));
+        mbc.write8(&mut [], 0x0000, 0x0A);
+        mbc.write8(&mut [], 0x4000, RTC_S);
+
+        advance(&time, 5);
+        assert_eq!(mbc.read8(&[], &[], 0xA000), 0);
+
+        /* Writing 1 without a 0 first does nothing */
+        mbc.write8(&mut [], 0x6000, 0x01);
+        assert_eq!(mbc.read8(&[], &[], 0xA000), 0);
+
+        mbc.write8(&mut [], 0x6000, 0x00);
+        mbc.write8(&mut [], 0x6000, 0x01);
+        assert_eq!(mbc.read8(&[], &[], 0xA000), 5);
+
+        /* The latched copy holds still until the next 0 then 1 */
+        advance(&time, 3);
+        assert_eq!(mbc.read8(&[], &[], 0xA000), 5);
+        mbc.write8(&mut [], 0x6000, 0x01);
+        assert_eq!(mbc.read8(&[], &[], 0xA000), 5);
+        mbc.write8(&mut [], 0x6000, 0x00);
+        mbc.write8(&mut [], 0x6000, 0x01);
+        assert_eq!(mbc.read8(&[], &[], 0xA000), 8);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let (mut rtc, time) = fake_rtc(0);
+
+        rtc.write(RTC_DH, DH_HALT);
+        advance(&time, 100);
+        rtc.latch();
+        assert_eq!(rtc.read(RTC_S), 0);
+        assert_eq!(rtc.read(RTC_DH), DH_HALT);
+
+        /* Time spent halted is not made up for */
+        rtc.write(RTC_DH, 0);
+        advance(&time, 10);
+        rtc.latch();
+        assert_eq!(rtc.read(RTC_S), 10);
+    }
+
+    #[test]
+    fn out_of_range_values_wrap_without_carry() {
+        let (mut rtc, time) = fake_rtc(0);
+
+        rtc.write(RTC_S, 0x3F);
+        rtc.write(RTC_H, 0x1F);
+        advance(&time, 1);
+        rtc.latch();
+        assert_eq!(rtc.read(RTC_S), 0);
+        assert_eq!(rtc.read(RTC_M), 0);
+        assert_eq!(rtc.read(RTC_H), 0x1F);
+
+        /* Back in range they count normally */
+        advance(&time, 61);
+        rtc.latch();
+        assert_eq!(rtc.read(RTC_S), 1);
+        assert_eq!(rtc.read(RTC_M), 1);
+    }
+
+    #[test]
+    fn day_counter_carry() {
+        let (mut rtc, time) = fake_rtc(0);
+
+        rtc.write(RTC_S, 59);
+        rtc.write(RTC_M, 59);
+        rtc.write(RTC_H, 23);
+        rtc.write(RTC_DL, 0xFF);
+        rtc.write(RTC_DH, DH_DAY_MSB);
+        advance(&time, 1);
+        rtc.latch();
+        assert_eq!(rtc.read(RTC_DL), 0);
+        assert_eq!(rtc.read(RTC_DH), DH_CARRY);
+
+        /* The carry sticks until it is written */
+        advance(&time, 86400);
+        rtc.latch();
+        assert_eq!(rtc.read(RTC_DL), 1);
+        assert_eq!(rtc.read(RTC_DH), DH_CARRY);
+        rtc.write(RTC_DH, 0);
+        rtc.latch();
+        assert_eq!(rtc.read(RTC_DH), 0);
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let (mut rtc, time) = fake_rtc(5000);
+        rtc.write(RTC_S, 10);
+        rtc.write(RTC_M, 20);
+        rtc.write(RTC_H, 3);
+        rtc.write(RTC_DL, 0x2A);
+        rtc.latch();
+        advance(&time, 2);
+        rtc.write(RTC_DH, DH_DAY_MSB);
+
+        let mut save = Vec::new();
+        rtc.save(&mut save);
+        assert_eq!(save.len(), RTC_SAVE_SIZE);
+        assert_eq!(&save[0..4], &[12, 0, 0, 0]);
+        assert_eq!(&save[20..24], &[10, 0, 0, 0]);
+        assert_eq!(&save[40..48], &5002u64.to_le_bytes());
+
+        /* Loaded a minute later, the time off counts */
+        let (mut loaded, _) = fake_rtc(5062);
+        loaded.load(&save);
+        assert_eq!(loaded.read(RTC_S), 10);
+        loaded.latch();
+        assert_eq!(loaded.read(RTC_S), 12);
+        assert_eq!(loaded.read(RTC_M), 21);
+        assert_eq!(loaded.read(RTC_H), 3);
+        assert_eq!(loaded.read(RTC_DL), 0x2A);
+        assert_eq!(loaded.read(RTC_DH), DH_DAY_MSB);
+    }
+}
@@ -0,0 +1,432 @@
+/* SM83 instruction decoder
+ *
+ * Opcodes are decoded from their bit fields rather than a 512 entry match:
+ *
+ *   7 6 | 5 4 3 | 2 1 0
+ *    x  |   y   |   z
+ *       | p | q |
+ *
+ * x selects the opcode block, y and z index into the operand tables below.
+ * https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+ */
+
+/* 8-bit register operands, in opcode encoding order */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum R8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    HLInd, /* (HL) */
+    A,
+}
+
+/* 16-bit register operands used by LD/INC/DEC/ADD */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum R16 {
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+/* 16-bit register operands used by PUSH/POP */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum R16Stack {
+    BC,
+    DE,
+    HL,
+    AF,
+}
+
+/* 16-bit indirect operands used by LD (r16), A and LD A, (r16) */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum R16Mem {
+    BC,
+    DE,
+    HLInc, /* (HL+) */
+    HLDec, /* (HL-) */
+}
+
+/* Branch conditions */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cond {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+/* Bit index operand for BIT/RES/SET, always 0-7 */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BitIndex(u8);
+
+/* Accumulator operations, x=2 block and the x=3 z=6 immediates */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+/* Rotate and shift operations, CB prefix x=0 block */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShiftOp {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+
+    /* 8-bit loads */
+    Ld(R8, R8),
+    LdImm(R8, u8),
+    LdMemA(R16Mem),       /* LD (r16), A */
+    LdAMem(R16Mem),       /* LD A, (r16) */
+    LdAbsA(u16),          /* LD (a16), A */
+    LdAAbs(u16),          /* LD A, (a16) */
+    LdhImmA(u8),          /* LDH (a8), A */
+    LdhAImm(u8),          /* LDH A, (a8) */
+    LdhCA,                /* LD (C), A */
+    LdhAC,                /* LD A, (C) */
+
+    /* 16-bit loads */
+    Ld16Imm(R16, u16),
+    LdAbsSp(u16),         /* LD (a16), SP */
+    LdSpHl,
+    LdHlSpOffset(i8),     /* LD HL, SP + e8 */
+    Push(R16Stack),
+    Pop(R16Stack),
+
+    /* 8-bit arithmetic */
+    Alu(AluOp, R8),
+    AluImm(AluOp, u8),
+    Inc(R8),
+    Dec(R8),
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+
+    /* 16-bit arithmetic */
+    Inc16(R16),
+    Dec16(R16),
+    AddHl(R16),
+    AddSp(i8),
+
+    /* Accumulator rotates, these always clear Z */
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+
+    /* CB prefixed */
+    Shift(ShiftOp, R8),
+    Bit(BitIndex, R8),
+    Res(BitIndex, R8),
+    Set(BitIndex, R8),
+
+    /* Control flow */
+    Jr(i8),
+    JrCond(Cond, i8),
+    Jp(u16),
+    JpCond(Cond, u16),
+    JpHl,
+    Call(u16),
+    CallCond(Cond, u16),
+    Ret,
+    RetCond(Cond),
+    Reti,
+    Rst(u8),
+
+    /* 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB-0xED, 0xF4, 0xFC, 0xFD */
+    Illegal(u8),
+}
+
+const R8_TABLE: [R8; 8] = [R8::B, R8::C, R8::D, R8::E, R8::H, R8::L, R8::HLInd, R8::A];
+const R16_TABLE: [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::SP];
+const R16_STACK_TABLE: [R16Stack; 4] = [R16Stack::BC, R16Stack::DE, R16Stack::HL, R16Stack::AF];
+const R16_MEM_TABLE: [R16Mem; 4] = [R16Mem::BC, R16Mem::DE, R16Mem::HLInc, R16Mem::HLDec];
+const COND_TABLE: [Cond; 4] = [Cond::NZ, Cond::Z, Cond::NC, Cond::C];
+const ALU_TABLE: [AluOp; 8] = [
+    AluOp::Add, AluOp::Adc, AluOp::Sub, AluOp::Sbc,
+    AluOp::And, AluOp::Xor, AluOp::Or, AluOp::Cp,
+];
+const SHIFT_TABLE: [ShiftOp; 8] = [
+    ShiftOp::Rlc, ShiftOp::Rrc, ShiftOp::Rl, ShiftOp::Rr,
+    ShiftOp::Sla, ShiftOp::Sra, ShiftOp::Swap, ShiftOp::Srl,
+];
+
+impl BitIndex {
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    pub fn mask(self) -> u8 {
+        1 << self.0
+    }
+}
+
+/* Length in bytes of the instruction starting with this opcode,
+ * including the 0xCB prefix byte */
+pub fn opcode_len(opcode: u8) -> usize {
+    match opcode {
+        0xCB => 2,
+        /* LD r16, u16 */
+        0x01 | 0x11 | 0x21 | 0x31 => 3,
+        /* LD (u16), SP */
+        0x08 => 3,
+        /* JP/CALL, conditional or not */
+        0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCC | 0xCD |
+        0xD2 | 0xD4 | 0xDA | 0xDC => 3,
+        /* LD (u16), A and LD A, (u16) */
+        0xEA | 0xFA => 3,
+        /* LD r8, u8 */
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => 2,
+        /* JR, conditional or not. STOP is followed by an ignored byte */
+        0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => 2,
+        /* ALU A, u8 */
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => 2,
+        /* LDH, ADD SP, LD HL, SP+i8 */
+        0xE0 | 0xF0 | 0xE8 | 0xF8 => 2,
+        _ => 1,
+    }
+}
+
+/* Decode the instruction at the start of bytes.  The slice must hold at
+ * least opcode_len(bytes[0]) bytes, anything past that is ignored. */
+pub fn decode(bytes: &[u8]) -> Instruction {
+    let op = bytes[0];
+    if op == 0xCB {
+        return decode_prefix(bytes[1]);
+    }
+
+    let x = op >> 6;
+    let y = (op >> 3) & 0x7;
+    let z = op & 0x7;
+    let p = (y >> 1) as usize;
+    let q = y & 0x1;
+
+    let imm8 = || bytes[1];
+    let imm16 = || (bytes[1] as u16) | ((bytes[2] as u16) << 8);
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => Instruction::Nop,
+            1 => Instruction::LdAbsSp(imm16()),
+            2 => Instruction::Stop,
+            3 => Instruction::Jr(imm8() as i8),
+            _ => Instruction::JrCond(COND_TABLE[(y - 4) as usize], imm8() as i8),
+        },
+        (0, 1) => if q == 0 {
+            Instruction::Ld16Imm(R16_TABLE[p], imm16())
+        } else {
+            Instruction::AddHl(R16_TABLE[p])
+        },
+        (0, 2) => if q == 0 {
+            Instruction::LdMemA(R16_MEM_TABLE[p])
+        } else {
+            Instruction::LdAMem(R16_MEM_TABLE[p])
+        },
+        (0, 3) => if q == 0 {
+            Instruction::Inc16(R16_TABLE[p])
+        } else {
+            Instruction::Dec16(R16_TABLE[p])
+        },
+        (0, 4) => Instruction::Inc(R8_TABLE[y as usize]),
+        (0, 5) => Instruction::Dec(R8_TABLE[y as usize]),
+        (0, 6) => Instruction::LdImm(R8_TABLE[y as usize], imm8()),
+        (0, _) => match y {
+            0 => Instruction::Rlca,
+            1 => Instruction::Rrca,
+            2 => Instruction::Rla,
+            3 => Instruction::Rra,
+            4 => Instruction::Daa,
+            5 => Instruction::Cpl,
+            6 => Instruction::Scf,
+            _ => Instruction::Ccf,
+        },
+        /* LD (HL), (HL) is where HALT lives */
+        (1, _) => if y == 6 && z == 6 {
+            Instruction::Halt
+        } else {
+            Instruction::Ld(R8_TABLE[y as usize], R8_TABLE[z as usize])
+        },
+        (2, _) => Instruction::Alu(ALU_TABLE[y as usize], R8_TABLE[z as usize]),
+        (_, 0) => match y {
+            0..=3 => Instruction::RetCond(COND_TABLE[y as usize]),
+            4 => Instruction::LdhImmA(imm8()),
+            5 => Instruction::AddSp(imm8() as i8),
+            6 => Instruction::LdhAImm(imm8()),
+            _ => Instruction::LdHlSpOffset(imm8() as i8),
+        },
+        (_, 1) => if q == 0 {
+            Instruction::Pop(R16_STACK_TABLE[p])
+        } else {
+            match p {
+                0 => Instruction::Ret,
+                1 => Instruction::Reti,
+                2 => Instruction::JpHl,
+                _ => Instruction::LdSpHl,
+            }
+        },
+        (_, 2) => match y {
+            0..=3 => Instruction::JpCond(COND_TABLE[y as usize], imm16()),
+            4 => Instruction::LdhCA,
+            5 => Instruction::LdAbsA(imm16()),
+            6 => Instruction::LdhAC,
+            _ => Instruction::LdAAbs(imm16()),
+        },
+        (_, 3) => match y {
+            0 => Instruction::Jp(imm16()),
+            6 => Instruction::Di,
+            7 => Instruction::Ei,
+            /* 1 is the 0xCB prefix, handled above */
+            _ => Instruction::Illegal(op),
+        },
+        (_, 4) => match y {
+            0..=3 => Instruction::CallCond(COND_TABLE[y as usize], imm16()),
+            _ => Instruction::Illegal(op),
+        },
+        (_, 5) => if q == 0 {
+            Instruction::Push(R16_STACK_TABLE[p])
+        } else if p == 0 {
+            Instruction::Call(imm16())
+        } else {
+            Instruction::Illegal(op)
+        },
+        (_, 6) => Instruction::AluImm(ALU_TABLE[y as usize], imm8()),
+        (_, _) => Instruction::Rst(y * 8),
+    }
+}
+
+fn decode_prefix(op: u8) -> Instruction {
+    let y = (op >> 3) & 0x7;
+    let reg = R8_TABLE[(op & 0x7) as usize];
+
+    match op >> 6 {
+        0 => Instruction::Shift(SHIFT_TABLE[y as usize], reg),
+        1 => Instruction::Bit(BitIndex(y), reg),
+        2 => Instruction::Res(BitIndex(y), reg),
+        _ => Instruction::Set(BitIndex(y), reg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(bytes: &[u8], len: usize, instr: Instruction) {
+        assert_eq!(opcode_len(bytes[0]), len, "length of {:02X?}", bytes);
+        assert_eq!(decode(bytes), instr, "decoding {:02X?}", bytes);
+    }
+
+    #[test]
+    fn each_block() {
+        use Instruction::*;
+
+        let cases = [
+            /* x=0 */
+            (0x00, 1, Nop),
+            (0x08, 3, LdAbsSp(0x1234)),
+            (0x10, 2, Stop),
+            (0x18, 2, Jr(0x34)),
+            (0x38, 2, JrCond(Cond::C, 0x34)),
+            (0x21, 3, Ld16Imm(R16::HL, 0x1234)),
+            (0x39, 1, AddHl(R16::SP)),
+            (0x02, 1, LdMemA(R16Mem::BC)),
+            (0x22, 1, LdMemA(R16Mem::HLInc)),
+            (0x3A, 1, LdAMem(R16Mem::HLDec)),
+            (0x13, 1, Inc16(R16::DE)),
+            (0x3B, 1, Dec16(R16::SP)),
+            (0x34, 1, Inc(R8::HLInd)),
+            (0x3D, 1, Dec(R8::A)),
+            (0x36, 2, LdImm(R8::HLInd, 0x34)),
+            (0x07, 1, Rlca),
+            (0x1F, 1, Rra),
+            (0x27, 1, Daa),
+            (0x3F, 1, Ccf),
+            /* x=1 */
+            (0x40, 1, Ld(R8::B, R8::B)),
+            (0x70, 1, Ld(R8::HLInd, R8::B)),
+            (0x76, 1, Halt),
+            (0x79, 1, Ld(R8::A, R8::C)),
+            (0x7E, 1, Ld(R8::A, R8::HLInd)),
+            /* x=2 */
+            (0x80, 1, Alu(AluOp::Add, R8::B)),
+            (0x9E, 1, Alu(AluOp::Sbc, R8::HLInd)),
+            (0xBF, 1, Alu(AluOp::Cp, R8::A)),
+            /* x=3 */
+            (0xC0, 1, RetCond(Cond::NZ)),
+            (0xE0, 2, LdhImmA(0x34)),
+            (0xE8, 2, AddSp(0x34)),
+            (0xF0, 2, LdhAImm(0x34)),
+            (0xF8, 2, LdHlSpOffset(0x34)),
+            (0xC1, 1, Pop(R16Stack::BC)),
+            (0xF1, 1, Pop(R16Stack::AF)),
+            (0xC9, 1, Ret),
+            (0xD9, 1, Reti),
+            (0xE9, 1, JpHl),
+            (0xF9, 1, LdSpHl),
+            (0xDA, 3, JpCond(Cond::C, 0x1234)),
+            (0xE2, 1, LdhCA),
+            (0xEA, 3, LdAbsA(0x1234)),
+            (0xF2, 1, LdhAC),
+            (0xFA, 3, LdAAbs(0x1234)),
+            (0xC3, 3, Jp(0x1234)),
+            (0xF3, 1, Di),
+            (0xFB, 1, Ei),
+            (0xCC, 3, CallCond(Cond::Z, 0x1234)),
+            (0xCD, 3, Call(0x1234)),
+            (0xE5, 1, Push(R16Stack::HL)),
+            (0xC6, 2, AluImm(AluOp::Add, 0x34)),
+            (0xFE, 2, AluImm(AluOp::Cp, 0x34)),
+            (0xC7, 1, Rst(0x00)),
+            (0xFF, 1, Rst(0x38)),
+        ];
+        for &(op, len, instr) in cases.iter() {
+            check(&[op, 0x34, 0x12], len, instr);
+        }
+
+        /* Relative jumps are signed */
+        check(&[0x20, 0xFE], 2, JrCond(Cond::NZ, -2));
+    }
+
+    #[test]
+    fn prefixed() {
+        use Instruction::*;
+
+        check(&[0xCB, 0x00], 2, Shift(ShiftOp::Rlc, R8::B));
+        check(&[0xCB, 0x36], 2, Shift(ShiftOp::Swap, R8::HLInd));
+        check(&[0xCB, 0x3F], 2, Shift(ShiftOp::Srl, R8::A));
+        check(&[0xCB, 0x7E], 2, Bit(BitIndex(7), R8::HLInd));
+        check(&[0xCB, 0x9F], 2, Res(BitIndex(3), R8::A));
+        check(&[0xCB, 0xFF], 2, Set(BitIndex(7), R8::A));
+    }
+
+    #[test]
+    fn illegal() {
+        for &op in [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD].iter() {
+            check(&[op, 0x00, 0x00], 1, Instruction::Illegal(op));
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use super::GBEmulator;
+use super::decoder::{decode, opcode_len, AluOp, Cond, Instruction, R16, R16Mem, R16Stack, R8, ShiftOp};
+
+/* Disassembly in RGBDS syntax, https://rgbds.gbdev.io/docs/gbz80.7 */
+
+const BANK_SIZE: usize = 0x4000;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Relative,
+    Jump,
+    Call,
+}
+
+/* Disassemble the instruction at addr, fetching bytes with read, returning
+ * the text and the instruction length in bytes */
+pub fn disassemble<F: FnMut(u16) -> u8>(mut read: F, addr: u16) -> (String, u16) {
+    let mut bytes = [0u8; 3];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = read(addr.wrapping_add(i as u16));
+    }
+
+    let len = opcode_len(bytes[0]) as u16;
+    let next_pc = addr.wrapping_add(len);
+    (format_instruction(decode(&bytes), next_pc, &|_| None), len)
+}
+
+impl GBEmulator {
+    /* Disassemble the instruction at addr as currently mapped */
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        disassemble(|addr| self.mmu_read8(addr), addr)
+    }
+}
+
+/* Write out a listing of the given cartridge banks.  Every call, jump and
+ * relative jump target that lands on an instruction gets a label. */
+pub fn disassemble_rom(rom: &[u8], banks: RangeInclusive<usize>) -> String {
+    let banks: Vec<usize> = banks.take_while(|bank| *bank < rom.len() / BANK_SIZE).collect();
+
+    /* First pass, find instruction boundaries and branch targets */
+    let mut starts: HashSet<(usize, u16)> = HashSet::new();
+    let mut targets: HashMap<(usize, u16), LabelKind> = HashMap::new();
+    for &bank in &banks {
+        for (addr, instr, raw) in BankInstructions::new(rom, bank) {
+            starts.insert((bank, addr));
+            let next_pc = addr.wrapping_add(raw.len() as u16);
+            if let Some((target, kind)) = branch_target(instr, next_pc) {
+                if let Some(key) = label_key(bank, target) {
+                    let entry = targets.entry(key).or_insert(kind);
+                    *entry = (*entry).max(kind);
+                }
+            }
+        }
+    }
+    targets.retain(|key, _| starts.contains(key));
+
+    /* Second pass, emit the listing */
+    let mut out = String::new();
+    for bank in banks {
+        if bank == 0 {
+            out.push_str("SECTION \"ROM Bank $000\", ROM0[$0000]\n");
+        } else {
+            out.push_str(&format!("SECTION \"ROM Bank ${:03x}\", ROMX[$4000], BANK[${:x}]\n", bank, bank));
+        }
+
+        let label = |target: u16| {
+            label_key(bank, target).and_then(|key| targets.get(&key).map(|kind| label_name(key, *kind)))
+        };
+
+        for (addr, instr, raw) in BankInstructions::new(rom, bank) {
+            if let Some(kind) = targets.get(&(bank, addr)) {
+                out.push_str(&format!("\n{}:\n", label_name((bank, addr), *kind)));
+            }
+
+            let next_pc = addr.wrapping_add(raw.len() as u16);
+            let text = format_instruction(instr, next_pc, &label);
+            let hex: Vec<String> = raw.iter().map(|byte| format!("{:02x}", byte)).collect();
+            out.push_str(&format!("    {:<32}; ${:04x}: {}\n", text, addr, hex.join(" ")));
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/* Linear sweep over one bank, yielding the CPU address, instruction and
+ * raw bytes.  An instruction running off the end of the bank becomes db. */
+struct BankInstructions<'a> {
+    bank: &'a [u8],
+    base: u16,
+    offset: usize,
+}
+
+impl<'a> BankInstructions<'a> {
+    fn new(rom: &'a [u8], bank: usize) -> BankInstructions<'a> {
+        BankInstructions {
+            bank: &rom[bank * BANK_SIZE..(bank + 1) * BANK_SIZE],
+            base: if bank == 0 { 0x0000 } else { 0x4000 },
+            offset: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for BankInstructions<'a> {
+    type Item = (u16, Instruction, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.bank.len() {
+            return None;
+        }
+
+        let addr = self.base + self.offset as u16;
+        let len = opcode_len(self.bank[self.offset]);
+        let (instr, len) = if self.offset + len > self.bank.len() {
+            (Instruction::Illegal(self.bank[self.offset]), 1)
+        } else {
+            (decode(&self.bank[self.offset..]), len)
+        };
+
+        let raw = &self.bank[self.offset..self.offset + len];
+        self.offset += len;
+        Some((addr, instr, raw))
+    }
+}
+
+fn branch_target(instr: Instruction, next_pc: u16) -> Option<(u16, LabelKind)> {
+    match instr {
+        Instruction::Jr(offset) | Instruction::JrCond(_, offset) => {
+            Some((relative_target(next_pc, offset), LabelKind::Relative))
+        },
+        Instruction::Jp(addr) | Instruction::JpCond(_, addr) => Some((addr, LabelKind::Jump)),
+        Instruction::Call(addr) | Instruction::CallCond(_, addr) => Some((addr, LabelKind::Call)),
+        Instruction::Rst(vector) => Some((vector as u16, LabelKind::Call)),
+        _ => None,
+    }
+}
+
+/* Work out which bank a target lands in as seen from code in bank.  Bank 0
+ * can't know which bank is switched in, so those targets get no label. */
+fn label_key(bank: usize, target: u16) -> Option<(usize, u16)> {
+    match target {
+        0x0000..=0x3FFF => Some((0, target)),
+        0x4000..=0x7FFF if bank != 0 => Some((bank, target)),
+        _ => None,
+    }
+}
+
+fn label_name((bank, addr): (usize, u16), kind: LabelKind) -> String {
+    let prefix = match kind {
+        LabelKind::Relative => "jr",
+        LabelKind::Jump => "Jump",
+        LabelKind::Call => "Call",
+    };
+    format!("{}_{:03x}_{:04x}", prefix, bank, addr)
+}
+
+fn relative_target(next_pc: u16, offset: i8) -> u16 {
+    next_pc.wrapping_add(offset as i16 as u16)
+}
+
+fn format_instruction(instr: Instruction, next_pc: u16, label: &dyn Fn(u16) -> Option<String>) -> String {
+    let target = |addr: u16| label(addr).unwrap_or_else(|| format!("${:04x}", addr));
+
+    match instr {
+        Instruction::Nop => "nop".to_string(),
+        Instruction::Stop => "stop".to_string(),
+        Instruction::Halt => "halt".to_string(),
+        Instruction::Di => "di".to_string(),
+        Instruction::Ei => "ei".to_string(),
+
+        Instruction::Ld(dst, src) => format!("ld {}, {}", r8_name(dst), r8_name(src)),
+        Instruction::LdImm(dst, value) => format!("ld {}, ${:02x}", r8_name(dst), value),
+        Instruction::LdMemA(reg) => format!("ld {}, a", r16_mem_name(reg)),
+        Instruction::LdAMem(reg) => format!("ld a, {}", r16_mem_name(reg)),
+        Instruction::LdAbsA(addr) => format!("ld [${:04x}], a", addr),
+        Instruction::LdAAbs(addr) => format!("ld a, [${:04x}]", addr),
+        Instruction::LdhImmA(offset) => format!("ldh [${:04x}], a", 0xFF00 | offset as u16),
+        Instruction::LdhAImm(offset) => format!("ldh a, [${:04x}]", 0xFF00 | offset as u16),
+        Instruction::LdhCA => "ldh [c], a".to_string(),
+        Instruction::LdhAC => "ldh a, [c]".to_string(),
+
+        Instruction::Ld16Imm(reg, value) => format!("ld {}, ${:04x}", r16_name(reg), value),
+        Instruction::LdAbsSp(addr) => format!("ld [${:04x}], sp", addr),
+        Instruction::LdSpHl => "ld sp, hl".to_string(),
+        Instruction::LdHlSpOffset(offset) => format!("ld hl, sp{}", signed_offset(offset)),
+        Instruction::Push(reg) => format!("push {}", r16_stack_name(reg)),
+        Instruction::Pop(reg) => format!("pop {}", r16_stack_name(reg)),
+
+        Instruction::Alu(op, reg) => format!("{}{}", alu_prefix(op), r8_name(reg)),
+        Instruction::AluImm(op, value) => format!("{}${:02x}", alu_prefix(op), value),
+        Instruction::Inc(reg) => format!("inc {}", r8_name(reg)),
+        Instruction::Dec(reg) => format!("dec {}", r8_name(reg)),
+        Instruction::Daa => "daa".to_string(),
+        Instruction::Cpl => "cpl".to_string(),
+        Instruction::Scf => "scf".to_string(),
+        Instruction::Ccf => "ccf".to_string(),
+
+        Instruction::Inc16(reg) => format!("inc {}", r16_name(reg)),
+        Instruction::Dec16(reg) => format!("dec {}", r16_name(reg)),
+        Instruction::AddHl(reg) => format!("add hl, {}", r16_name(reg)),
+        Instruction::AddSp(offset) => format!("add sp, {}", signed_offset(offset).trim_start_matches('+')),
+
+        Instruction::Rlca => "rlca".to_string(),
+        Instruction::Rrca => "rrca".to_string(),
+        Instruction::Rla => "rla".to_string(),
+        Instruction::Rra => "rra".to_string(),
+
+        Instruction::Shift(op, reg) => format!("{} {}", shift_name(op), r8_name(reg)),
+        Instruction::Bit(bit, reg) => format!("bit {}, {}", bit.index(), r8_name(reg)),
+        Instruction::Res(bit, reg) => format!("res {}, {}", bit.index(), r8_name(reg)),
+        Instruction::Set(bit, reg) => format!("set {}, {}", bit.index(), r8_name(reg)),
+
+        Instruction::Jr(offset) => format!("jr {}", target(relative_target(next_pc, offset))),
+        Instruction::JrCond(cond, offset) => {
+            format!("jr {}, {}", cond_name(cond), target(relative_target(next_pc, offset)))
+        },
+        Instruction::Jp(addr) => format!("jp {}", target(addr)),
+        Instruction::JpCond(cond, addr) => format!("jp {}, {}", cond_name(cond), target(addr)),
+        Instruction::JpHl => "jp hl".to_string(),
+        Instruction::Call(addr) => format!("call {}", target(addr)),
+        Instruction::CallCond(cond, addr) => format!("call {}, {}", cond_name(cond), target(addr)),
+        Instruction::Ret => "ret".to_string(),
+        Instruction::RetCond(cond) => format!("ret {}", cond_name(cond)),
+        Instruction::Reti => "reti".to_string(),
+        Instruction::Rst(vector) => format!("rst ${:02x}", vector),
+
+        Instruction::Illegal(op) => format!("db ${:02x}", op),
+    }
+}
+
+/* +$05 or -$03 */
+fn signed_offset(offset: i8) -> String {
+    if offset < 0 {
+        format!("-${:02x}", -(offset as i16))
+    } else {
+        format!("+${:02x}", offset)
+    }
+}
+
+fn r8_name(reg: R8) -> &'static str {
+    match reg {
+        R8::B => "b",
+        R8::C => "c",
+        R8::D => "d",
+        R8::E => "e",
+        R8::H => "h",
+        R8::L => "l",
+        R8::HLInd => "[hl]",
+        R8::A => "a",
+    }
+}
+
+fn r16_name(reg: R16) -> &'static str {
+    match reg {
+        R16::BC => "bc",
+        R16::DE => "de",
+        R16::HL => "hl",
+        R16::SP => "sp",
+    }
+}
+
+fn r16_stack_name(reg: R16Stack) -> &'static str {
+    match reg {
+        R16Stack::BC => "bc",
+        R16Stack::DE => "de",
+        R16Stack::HL => "hl",
+        R16Stack::AF => "af",
+    }
+}
+
+fn r16_mem_name(reg: R16Mem) -> &'static str {
+    match reg {
+        R16Mem::BC => "[bc]",
+        R16Mem::DE => "[de]",
+        R16Mem::HLInc => "[hl+]",
+        R16Mem::HLDec => "[hl-]",
+    }
+}
+
+fn cond_name(cond: Cond) -> &'static str {
+    match cond {
+        Cond::NZ => "nz",
+        Cond::Z => "z",
+        Cond::NC => "nc",
+        Cond::C => "c",
+    }
+}
+
+/* add/adc/sbc name A explicitly, the rest only take the source */
+fn alu_prefix(op: AluOp) -> &'static str {
+    match op {
+        AluOp::Add => "add a, ",
+        AluOp::Adc => "adc a, ",
+        AluOp::Sub => "sub ",
+        AluOp::Sbc => "sbc a, ",
+        AluOp::And => "and ",
+        AluOp::Xor => "xor ",
+        AluOp::Or => "or ",
+        AluOp::Cp => "cp ",
+    }
+}
+
+fn shift_name(op: ShiftOp) -> &'static str {
+    match op {
+        ShiftOp::Rlc => "rlc",
+        ShiftOp::Rrc => "rrc",
+        ShiftOp::Rl => "rl",
+        ShiftOp::Rr => "rr",
+        ShiftOp::Sla => "sla",
+        ShiftOp::Sra => "sra",
+        ShiftOp::Swap => "swap",
+        ShiftOp::Srl => "srl",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8]) -> String {
+        let next_pc = opcode_len(bytes[0]) as u16;
+        format_instruction(decode(bytes), next_pc, &|_| None)
+    }
+
+    /* The listing line for the instruction at addr */
+    fn listed(out: &str, text: &str, addr: u16) -> bool {
+        out.contains(&format!("    {:<32}; ${:04x}: ", text, addr))
+    }
+
+    #[test]
+    fn rgbds_syntax() {
+        assert_eq!(text(&[0xE0, 0x40]), "ldh [$ff40], a");
+        assert_eq!(text(&[0xF2]), "ldh a, [c]");
+        assert_eq!(text(&[0x2A]), "ld a, [hl+]");
+        assert_eq!(text(&[0xFA, 0x34, 0x12]), "ld a, [$1234]");
+        assert_eq!(text(&[0xF8, 0xFE]), "ld hl, sp-$02");
+        assert_eq!(text(&[0xE8, 0x05]), "add sp, $05");
+        assert_eq!(text(&[0xE8, 0x80]), "add sp, -$80");
+        assert_eq!(text(&[0x8E]), "adc a, [hl]");
+        assert_eq!(text(&[0x90]), "sub b");
+        assert_eq!(text(&[0xCB, 0x7C]), "bit 7, h");
+        assert_eq!(text(&[0xDF]), "rst $18");
+        assert_eq!(text(&[0x18, 0xFE]), "jr $0000");
+        assert_eq!(text(&[0xD3]), "db $d3");
+    }
+
+    #[test]
+    fn labels_are_bank_relative() {
+        let mut rom = vec![0; 2 * BANK_SIZE];
+        rom[0x0000..0x000B].copy_from_slice(&[
+            0xCD, 0x10, 0x00, /* call $0010 */
+            0x18, 0xFE,       /* jr to itself */
+            0xC3, 0x00, 0x40, /* jp $4000, bank unknown from bank 0 */
+            0xC3, 0x01, 0x00, /* jp $0001, inside the call */
+        ]);
+        rom[0x0010] = 0xC9;
+        rom[0x4000..0x4006].copy_from_slice(&[
+            0xC3, 0x03, 0x40, /* jp $4003 */
+            0xCD, 0x10, 0x00, /* call $0010 */
+        ]);
+        /* Runs off the end of the bank */
+        rom[0x7FFF] = 0xC3;
+
+        let out = disassemble_rom(&rom, 0..=5);
+        assert!(out.contains("SECTION \"ROM Bank $000\", ROM0[$0000]\n"));
+        assert!(out.contains("SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]\n"));
+        assert!(!out.contains("ROM Bank $002"));
+
+        assert!(out.contains("\nCall_000_0010:\n"));
+        assert!(out.contains("\njr_000_0003:\n"));
+        assert!(out.contains("\nJump_001_4003:\n"));
+        assert!(!out.contains("_0001:"));
+
+        assert!(listed(&out, "call Call_000_0010", 0x0000));
+        assert!(listed(&out, "jr jr_000_0003", 0x0003));
+        assert!(listed(&out, "jp $4000", 0x0005));
+        assert!(listed(&out, "jp $0001", 0x0008));
+        assert!(listed(&out, "jp Jump_001_4003", 0x4000));
+        assert!(listed(&out, "call Call_000_0010", 0x4003));
+        assert!(out.contains(&format!("    {:<32}; $7fff: c3\n", "db $c3")));
+    }
+}
@@ -0,0 +1,168 @@
+use super::GBEmulator;
+
+const OAM: u16         = 0xFE00;
+const OAM_SIZE: u16    = 0xA0;
+
+/* OAM DMA, started by writing the source page to 0xFF46.  After a cycle to
+ * start up it copies one byte per M-cycle for 160 M-cycles.  Restarting it
+ * mid transfer keeps the bus locked through the new start up cycle.
+ * https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+ */
+pub struct OamDma {
+    source: u16,
+    /* Next byte to copy */
+    index: u16,
+    /* M-cycles before the first byte is copied */
+    delay: u8,
+    /* Last byte copied, this is what the CPU sees on a conflicting read */
+    value: u8,
+    /* Set once bytes are moving, the CPU is locked out from then on */
+    locking: bool,
+}
+
+impl GBEmulator {
+    pub fn dma_start(&mut self, value: u8) {
+        let (value_on_bus, locking) = match &self.dma {
+            Some(dma) => (dma.value, dma.locking),
+            None => (0xFF, false),
+        };
+        self.dma = Some(OamDma {
+            source: (value as u16) << 8,
+            index: 0,
+            delay: 1,
+            value: value_on_bus,
+            locking,
+        });
+    }
+
+    pub fn dma_run(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            let mut dma = match self.dma.take() {
+                Some(dma) => dma,
+                None => return,
+            };
+
+            /* The bus is let go the cycle after the last byte */
+            if dma.index == OAM_SIZE {
+                return;
+            }
+
+            if dma.delay > 0 {
+                dma.delay -= 1;
+            } else {
+                dma.value = self.dma_read(dma.source + dma.index);
+                self.mem[(OAM + dma.index) as usize] = dma.value;
+                dma.index += 1;
+                dma.locking = true;
+            }
+            self.dma = Some(dma);
+        }
+    }
+
+    /* While a transfer is running the CPU only has the 0xFF00-0xFFFF page
+     * to itself, that is I/O, HRAM and IE.  OAM reads back 0xFF and the
+     * rest reads whatever byte the DMA is moving.  Returns None when the
+     * access goes through as normal. */
+    pub fn dma_bus_conflict(&self, addr: u16) -> Option<u8> {
+        match &self.dma {
+            Some(dma) if dma.locking && addr < 0xFF00 => match addr {
+                0xFE00 ..= 0xFEFF => Some(0xFF),
+                _ => Some(dma.value),
+            },
+            _ => None,
+        }
+    }
+
+    /* The DMA sees work RAM above 0xDFFF, not OAM and I/O */
+    fn dma_read(&self, addr: u16) -> u8 {
+        if addr >= 0xE000 {
+            self.mmu_read8(addr - 0x2000)
+        } else {
+            self.mmu_read8(addr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Bus;
+
+    /* A pattern in work RAM at 0xC000 to copy from */
+    fn gb_with_source() -> GBEmulator {
+        let mut gb = GBEmulator::blank();
+        for i in 0..OAM_SIZE as usize {
+            gb.mem[0xC000 + i] = i as u8 ^ 0x5A;
+        }
+        gb
+    }
+
+    #[test]
+    fn timing() {
+        let mut gb = gb_with_source();
+        gb.dma_start(0xC0);
+
+        /* Nothing moves or locks during the start up cycle */
+        gb.dma_run(4);
+        assert_eq!(gb.mem[0xFE00], 0);
+        assert_eq!(gb.dma_bus_conflict(0xC000), None);
+
+        for i in 0..OAM_SIZE as usize {
+            gb.dma_run(4);
+            assert_eq!(gb.mem[0xFE00 + i], i as u8 ^ 0x5A);
+            if i + 1 < OAM_SIZE as usize {
+                assert_eq!(gb.mem[0xFE00 + i + 1], 0);
+            }
+            assert!(gb.dma_bus_conflict(0xC000).is_some());
+        }
+
+        /* The bus is let go the cycle after the last byte */
+        gb.dma_run(4);
+        assert!(gb.dma.is_none());
+        assert_eq!(gb.dma_bus_conflict(0xC000), None);
+    }
+
+    #[test]
+    fn bus_conflicts() {
+        let mut gb = gb_with_source();
+        gb.mem[0x8000] = 0x11;
+        gb.mem[0xFF80] = 0x22;
+        gb.dma_start(0xC0);
+        gb.dma_run(8);
+
+        /* OAM reads 0xFF, the rest below 0xFF00 the byte being moved */
+        assert_eq!(gb.dma_bus_conflict(0xFE10), Some(0xFF));
+        assert_eq!(gb.dma_bus_conflict(0x0000), Some(0x5A));
+        assert_eq!(gb.dma_bus_conflict(0x8000), Some(0x5A));
+        assert_eq!(gb.dma_bus_conflict(0xC123), Some(0x5A));
+        assert_eq!(gb.dma_bus_conflict(0xFEFF), Some(0xFF));
+        assert_eq!(gb.dma_bus_conflict(0xFF00), None);
+        assert_eq!(gb.dma_bus_conflict(0xFF80), None);
+        assert_eq!(gb.dma_bus_conflict(0xFFFF), None);
+
+        assert_eq!(Bus::read8(&mut gb, 0x8000), 0x5A);
+        Bus::write8(&mut gb, 0xC100, 0x33);
+        assert_eq!(gb.mem[0xC100], 0);
+
+        /* HRAM stays reachable */
+        assert_eq!(Bus::read8(&mut gb, 0xFF80), 0x22);
+        Bus::write8(&mut gb, 0xFF81, 0x44);
+        assert_eq!(gb.mem[0xFF81], 0x44);
+    }
+
+    #[test]
+    fn echo_ram_source() {
+        let mut gb = gb_with_source();
+        gb.dma_start(0xE0);
+        gb.dma_run(12);
+        assert_eq!(gb.mem[0xFE00], 0x5A);
+        assert_eq!(gb.mem[0xFE01], 0x5B);
+
+        /* 0xFE00 and up is work RAM too, not OAM */
+        gb.mem[0xDE00] = 0x77;
+        gb.dma = None;
+        gb.dma_start(0xFE);
+        gb.dma_run(8);
+        assert_eq!(gb.mem[0xFE00], 0x77);
+    }
+}
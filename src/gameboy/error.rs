@@ -0,0 +1,57 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    Io(io::Error),
+    /* The boot ROM must be exactly 256 bytes */
+    InvalidBootRom(usize),
+    /* The ROM header is unusable, e.g. an unknown ROM size code */
+    InvalidRom(String),
+    /* The ROM is shorter than its header, or than the size it declares */
+    TruncatedRom { expected: usize, actual: usize },
+    BadHeaderChecksum { expected: u8, actual: u8 },
+    /* Cartridge type byte at 0x147 */
+    UnsupportedMapper(u8),
+    /* An illegal opcode hung the CPU, addr is where it was fetched from */
+    CpuLocked { opcode: u8, addr: u16 },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::Io(err) => write!(f, "I/O error: {}", err),
+            EmulatorError::InvalidBootRom(len) => {
+                write!(f, "Boot ROM is {} bytes, expected 256", len)
+            },
+            EmulatorError::InvalidRom(reason) => write!(f, "Invalid ROM: {}", reason),
+            EmulatorError::TruncatedRom { expected, actual } => {
+                write!(f, "ROM is truncated, expected {} bytes but got {}", expected, actual)
+            },
+            EmulatorError::BadHeaderChecksum { expected, actual } => {
+                write!(f, "Bad header checksum, expected {:#04X} but got {:#04X}", expected, actual)
+            },
+            EmulatorError::UnsupportedMapper(kind) => {
+                write!(f, "Unsupported cartridge type {:#04X}", kind)
+            },
+            EmulatorError::CpuLocked { opcode, addr } => {
+                write!(f, "CPU locked up by illegal opcode {:#04X} at {:#06X}", opcode, addr)
+            },
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EmulatorError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(err: io::Error) -> Self {
+        EmulatorError::Io(err)
+    }
+}
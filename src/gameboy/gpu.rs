@@ -1,4 +1,5 @@
 use super::GBEmulator;
+use super::interrupts::Interrupt;
 
 /*const LIGHTEST: [u8; 4] = [155, 188, 15, 255];
 const LIGHT: [u8; 4] = [139, 172, 15, 255];
@@ -15,103 +16,195 @@ const SCY: u16      = 0xFF42;
 const SCX: u16      = 0xFF43;
 const LY: u16       = 0xFF44;
 const LYC: u16      = 0xFF45;
-const DMA: u16      = 0xFF46;
 const BGP: u16      = 0xFF47;
 const OBP0: u16     = 0xFF48;
 const OBP1: u16     = 0xFF49;
 const WY: u16       = 0xFF4A;
 const WX: u16       = 0xFF4B;
 
+const OAM: u16      = 0xFE00;
+
+const OAM_SPRITES: u16         = 40;
+const SPRITES_PER_LINE: usize  = 10;
+
+/* OAM attribute flags */
+const OBJ_BEHIND_BG: u8 = 1 << 7;
+const OBJ_Y_FLIP: u8    = 1 << 6;
+const OBJ_X_FLIP: u8    = 1 << 5;
+const OBJ_PALETTE: u8   = 1 << 4;
+
 const VBLANK_SCANLINE: u8       = 144;
 const VBLANK_SCANLINE_MAX: u8   = 153;
 const GPU_CYCLES_PER_FRAME: u32 = 456;
 
+/* An OAM entry, positions are as stored so X is 8 and Y 16 past the
+ * screen coordinate */
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    flags: u8,
+}
+
+impl Sprite {
+    /* Line of the sprite that ly falls on, if it is in 0..height */
+    fn row(&self, ly: u8) -> i16 {
+        ly as i16 + 16 - self.y as i16
+    }
+}
+
 impl GBEmulator {
     pub fn draw_scanline(&mut self) {
         let lcdc = self.mmu_read8(LCDC);
+        let ly = self.mmu_read8(LY);
 
-        if lcdc & (1 << 0) == 0 {
-            /* LCD is off, should I draw a color across the frame here? */
-            return;
+        /* Colour numbers before the palette, sprites need them to
+         * work out their priority against the background */
+        let mut bg_values = [0u8; 160];
+        if lcdc & (1 << 0) != 0 {
+            self.draw_background(ly, &mut bg_values);
+        } else {
+            /* Background and window off, they go blank */
+            for pixel in 0..160 {
+                self.set_pixel(pixel, ly, LIGHTEST);
+            }
+        }
+
+        if lcdc & (1 << 1) != 0 {
+            self.draw_sprites(ly, &bg_values);
         }
-        
+    }
+
+    fn draw_background(&mut self, ly: u8, bg_values: &mut [u8; 160]) {
+        let lcdc = self.mmu_read8(LCDC);
         let wy = self.mmu_read8(WY);
         let wx = self.mmu_read8(WX);
-        let ly = self.mmu_read8(LY);
         let scy = self.mmu_read8(SCY);
         let scx = self.mmu_read8(SCX);
 
-        /* Set to true if a window is enabled and visible */
-        let draw_window: bool = if lcdc & (1 << 5) != 0 {
-            wy <= ly
-        } else {
-            false
-        };
-
-        /* Some tile regions use signed or unsigned addresses */
-        let (tile_offset, signed) = if lcdc & (1 << 4) != 0 {
-            (0x8000, false)
-        } else {
-            (0x8800, true)
-        };
+        /* The window covers everything from WX - 7 to the right, on lines
+         * from WY down.  It is its own 32x32 tile map drawn from the top
+         * left, and doesn't scroll. */
+        let window_x = wx as i16 - 7;
+        let draw_window = lcdc & (1 << 5) != 0 && ly >= wy && wx <= 166;
 
-        assert!(!signed);
+        let bg_map = if lcdc & (1 << 3) != 0 { 0x9C00 } else { 0x9800 };
+        let window_map = if lcdc & (1 << 6) != 0 { 0x9C00 } else { 0x9800 };
+        let bgp = self.mmu_read8(BGP);
 
-        let bg_offset = if draw_window {
-            if lcdc & (1 << 3) != 0 {
-                0x9C00
+        for (pixel, bg_value) in bg_values.iter_mut().enumerate() {
+            let value = if draw_window && pixel as i16 >= window_x {
+                let xpos = (pixel as i16 - window_x) as u8;
+                self.tile_map_pixel(lcdc, window_map, xpos, self.window_line)
             } else {
-                0x9800
-            }
-        } else if lcdc & (1 << 6) != 0 {
-            0x9C00
-        } else {
-            0x9800
-        };
+                let xpos = (pixel as u8).wrapping_add(scx);
+                self.tile_map_pixel(lcdc, bg_map, xpos, ly.wrapping_add(scy))
+            };
+
+            let pixel_color = self.get_color_from_palette(value, bgp);
+            
+            //println!("ly {}, pixel {}", ly, pixel);
+            *bg_value = value;
+            self.set_pixel(pixel, ly, pixel_color);
+        }
 
-        let ypos = if draw_window {
-            ly.wrapping_sub(wy)
+        /* The window keeps its own line counter, so it picks up where it
+         * left off on lines it was hidden for */
+        if draw_window {
+            self.window_line = self.window_line.wrapping_add(1);
+        }
+    }
+
+    /* Colour number at xpos, ypos in a 256x256 tile map */
+    fn tile_map_pixel(&self, lcdc: u8, map: u16, xpos: u8, ypos: u8) -> u8 {
+        /* Tile numbers are unsigned from 0x8000, or signed from 0x9000
+         * so that 0x80-0xFF land in 0x8800-0x8FFF */
+        let tile_num = self.mmu_read8(map + (ypos as u16 / 8) * 32 + xpos as u16 / 8);
+        let tile_location: u16 = if lcdc & (1 << 4) != 0 {
+            0x8000 + tile_num as u16 * 16
         } else {
-            ly.wrapping_add(scy)
+            0x9000u16.wrapping_add((tile_num as i8 as i16 as u16).wrapping_mul(16))
         };
+        let line = (ypos as u16 % 8) * 2;
 
-        let tile_row = ((ypos as u16)/8) * 32;
-        for pixel in 0..160 {
-            let xpos: u16 = if draw_window && (pixel as u16) >= (wx as u16) {
-                //i.saturating_sub(wx.into())
-                (pixel as u16) - (wx as u16)
-            } else {
-                (pixel as u16) + (scx as u16)
-            };
+        let byte0 = self.mmu_read8(tile_location + line);
+        let byte1 = self.mmu_read8(tile_location + line + 1);
 
-            let tile_col = xpos / 8;
-            let tile_addr: u16 = (bg_offset + tile_row + tile_col) as u16;
-            let tile_num = self.mmu_read8(tile_addr) as u16;
-            let tile_location: u16 = tile_offset + (tile_num * 16);
-            let line = ((ypos as u16) % 8) * 2;
+        let bit = 7 - xpos % 8;
+        ((byte1 >> bit) & 1) << 1 | ((byte0 >> bit) & 1)
+    }
 
-            let byte0 =  self.mmu_read8(tile_location + line);
-            let byte1 =  self.mmu_read8(tile_location + line + 1);
+    /* Sprites are picked by the OAM scan at the start of each line, the
+     * first 10 in OAM that cover it.  Where they overlap the one with the
+     * lowest X wins, then the one first in OAM, even if it ends up behind
+     * the background. */
+    fn draw_sprites(&mut self, ly: u8, bg_values: &[u8; 160]) {
+        let height = if self.mmu_read8(LCDC) & (1 << 2) != 0 { 16 } else { 8 };
 
-            let bit = 7 - (xpos as u8) % 8;
+        let mut sprites: Vec<Sprite> = (0..OAM_SPRITES)
+            .map(|index| self.oam_sprite(index))
+            .filter(|sprite| (0..height).contains(&sprite.row(ly)))
+            .take(SPRITES_PER_LINE)
+            .collect();
+        /* Stable, so OAM order is kept for equal X */
+        sprites.sort_by_key(|sprite| sprite.x);
 
-            let mut value = (byte0 & bit) >> bit;
-            value |= ((byte1 & bit) >> bit) << 1;
+        for (pixel, &bg_value) in bg_values.iter().enumerate() {
+            for sprite in &sprites {
+                let col = pixel as i16 + 8 - sprite.x as i16;
+                if !(0..8).contains(&col) {
+                    continue;
+                }
 
-            let bgp = self.mmu_read8(BGP);
+                /* Colour 0 is transparent, look further down the list */
+                let value = self.sprite_pixel(sprite, sprite.row(ly), col, height);
+                if value == 0 {
+                    continue;
+                }
 
-            let pixel_color = self.get_color_from_palette(value, bgp);
-            
-            //println!("ly {}, pixel {}", ly, pixel);
-            let pixel_offset = ((144 * (ly as usize)) + pixel) * 4;
+                if sprite.flags & OBJ_BEHIND_BG == 0 || bg_value == 0 {
+                    let palette = if sprite.flags & OBJ_PALETTE != 0 { OBP1 } else { OBP0 };
+                    let color = self.get_color_from_palette(value, self.mmu_read8(palette));
+                    self.set_pixel(pixel, ly, color);
+                }
+                break;
+            }
+        }
+    }
 
-            //println!("pixel_offset {}", pixel_offset);
-            self.framebuffer[pixel_offset] = pixel_color[0];
-            self.framebuffer[pixel_offset + 1] = pixel_color[1];
-            self.framebuffer[pixel_offset + 2] = pixel_color[2];
+    fn oam_sprite(&self, index: u16) -> Sprite {
+        let addr = OAM + index * 4;
+        Sprite {
+            y: self.mmu_read8(addr),
+            x: self.mmu_read8(addr + 1),
+            tile: self.mmu_read8(addr + 2),
+            flags: self.mmu_read8(addr + 3),
         }
     }
 
+    /* Colour number of a sprite pixel, row and col are before flipping.
+     * Sprite tiles always use 0x8000 addressing, tall sprites ignore the
+     * low bit of the tile number. */
+    fn sprite_pixel(&self, sprite: &Sprite, row: i16, col: i16, height: i16) -> u8 {
+        let row = if sprite.flags & OBJ_Y_FLIP != 0 { height - 1 - row } else { row };
+        let bit = if sprite.flags & OBJ_X_FLIP != 0 { col } else { 7 - col };
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+
+        let addr = 0x8000 + tile as u16 * 16 + row as u16 * 2;
+        let byte0 = self.mmu_read8(addr);
+        let byte1 = self.mmu_read8(addr + 1);
+        ((byte1 >> bit) & 1) << 1 | ((byte0 >> bit) & 1)
+    }
+
+    fn set_pixel(&mut self, x: usize, y: u8, color: [u8; 4]) {
+        let pixel_offset = ((160 * (y as usize)) + x) * 4;
+
+        //println!("pixel_offset {}", pixel_offset);
+        self.framebuffer[pixel_offset] = color[0];
+        self.framebuffer[pixel_offset + 1] = color[1];
+        self.framebuffer[pixel_offset + 2] = color[2];
+    }
+
     fn get_color_from_palette(&self, value: u8, palette: u8) -> [u8; 4] {
         let shade = (palette >> (value * 2)) & 0x3;
         match shade {
@@ -123,53 +216,75 @@ impl GBEmulator {
         }
     }
 
+    /* Fill the frame with the lightest shade, as seen while the LCD is off */
+    pub fn blank_frame(&mut self) {
+        for pixel in self.framebuffer.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&LIGHTEST);
+        }
+    }
+
+    /* Mode from STAT, 0 while the LCD is off */
+    pub fn ppu_mode(&self) -> u8 {
+        if self.mmu_read8(LCDC) & (1 << 7) == 0 {
+            return 0;
+        }
+        self.mmu_read8(STAT) & 0x3
+    }
+
     pub fn gpu_run(&mut self, cycles: u32) {
+        if self.stopped {
+            return;
+        }
+
         let lcdc = self.mmu_read8(LCDC);
         if lcdc & (1 << 7) == 0 { /* LCD Off */
             self.gpu_frame_cycles = GPU_CYCLES_PER_FRAME;
+            self.window_line = 0;
             self.mmu_write8(LY, 0x0);
-            self.mmu_write8(STAT,0x1);
+            self.mem[STAT as usize] &= !0x3;
             return;
         }
 
         self.set_lcd_stat();
 
-        let (result, overflow) = self.gpu_frame_cycles.overflowing_sub(cycles);
-        if overflow {
-            self.gpu_frame_cycles = 0;
+        /* A line is 456 clocks, anything past the end of this one counts
+         * towards the next */
+        if cycles >= self.gpu_frame_cycles {
+            self.gpu_frame_cycles = GPU_CYCLES_PER_FRAME - (cycles - self.gpu_frame_cycles);
             let mut ly = self.mmu_read8(LY);
+            /* Finish off the line being left, if it is on screen */
+            if ly < VBLANK_SCANLINE {
+                self.draw_scanline();
+            }
             ly += 1;
-            self.gpu_frame_cycles = GPU_CYCLES_PER_FRAME;
 
             if ly == VBLANK_SCANLINE {
-                self.request_irq(0);
+                self.request_irq(Interrupt::VBlank);
             } else if ly > VBLANK_SCANLINE_MAX {
                 ly = 0;
-            } else {
-                self.draw_scanline();
+                self.window_line = 0;
             }
 
             self.mmu_write8(LY, ly);
 
         } else {
-            self.gpu_frame_cycles = result;
+            self.gpu_frame_cycles -= cycles;
         }
     }
 
     fn set_lcd_stat(&mut self) {
         let mut lcd_status = self.mmu_read8(STAT);
-        let lcdc = self.mmu_read8(LCDC);
-        let current_mode = lcdc & 0x3;
+        let current_mode = lcd_status & 0x3;
         let ly = self.mmu_read8(LY);
 
         /* Check LCD Status mode, if we are entering
          * a new mode with an IRQ enabled, request it */
-        if ly > VBLANK_SCANLINE {
+        if ly >= VBLANK_SCANLINE {
             /* VBLANK mode */
             if current_mode != 1  {
-                lcd_status = (lcd_status & 0x3) | 0x1;
+                lcd_status = (lcd_status & !0x3) | 0x1;
                 if (current_mode != 1) && (lcd_status & (1 << 4) != 0) {
-                    self.request_irq(1);
+                    self.request_irq(Interrupt::Stat);
                 }
             }
         } else {
@@ -178,31 +293,390 @@ impl GBEmulator {
 
             if self.gpu_frame_cycles >= MODE2_START {
                 /* OAM */
-                lcd_status = (lcd_status & 0x3) | 0x2;
+                lcd_status = (lcd_status & !0x3) | 0x2;
                 if current_mode != 2 && (lcd_status & (1 << 5) != 0) {
-                    self.request_irq(1);
+                    self.request_irq(Interrupt::Stat);
                 }
             } else if self.gpu_frame_cycles > MODE3_START {
                 /* LCD */
-                lcd_status = (lcd_status & 0x3) | 0x3;
+                lcd_status = (lcd_status & !0x3) | 0x3;
             } else {
                 /* HBLANK */
-                lcd_status = lcd_status & 0x3;
-                if current_mode != 3 && (lcd_status & (1 << 3) != 0) {
-                    self.request_irq(1);
+                lcd_status &= !0x3;
+                if current_mode != 0 && (lcd_status & (1 << 3) != 0) {
+                    self.request_irq(Interrupt::Stat);
                 }
             }
         }
 
-        /* Coincidence IRQ */
+        /* Coincidence IRQ, on the rising edge only as this runs every
+         * M-cycle */
         if ly == self.mmu_read8(LYC) {
-            lcd_status |= 1 << 2;
-            if lcd_status & (1 << 6) != 0 {
-                self.request_irq(1);
+            if lcd_status & (1 << 2) == 0 && lcd_status & (1 << 6) != 0 {
+                self.request_irq(Interrupt::Stat);
             }
+            lcd_status |= 1 << 2;
         } else {
             lcd_status &= !(1 << 2);
         }
-        self.mmu_write8(STAT, lcd_status);
+        /* Not through the MMU, which keeps the CPU off the mode bits */
+        self.mem[STAT as usize] = lcd_status;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IF: usize = 0xFF0F;
+    const STAT_IRQ: u8 = 1 << 1;
+
+    /* Run a whole frame an M-cycle at a time, counting STAT requests */
+    fn stat_irqs_in_frame(gb: &mut GBEmulator) -> u32 {
+        let mut count = 0;
+        for _ in 0..154 * GPU_CYCLES_PER_FRAME / 4 {
+            gb.gpu_run(4);
+            if gb.mem[IF] & STAT_IRQ != 0 {
+                gb.mem[IF] &= !STAT_IRQ;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn lyc_irq_once_per_match() {
+        let mut gb = GBEmulator::blank();
+        gb.mmu_write8(LCDC, 0x80);
+        gb.mmu_write8(STAT, 1 << 6);
+        gb.mmu_write8(LYC, 5);
+
+        assert_eq!(stat_irqs_in_frame(&mut gb), 1);
+        assert_eq!(stat_irqs_in_frame(&mut gb), 1);
+    }
+
+    /* Line 0 with the background off, sprites on and OBP0 mapping each
+     * colour to the shade of the same number.  OBP1 is the reverse. */
+    fn sprite_gb() -> GBEmulator {
+        let mut gb = GBEmulator::blank();
+        gb.mmu_write8(LCDC, 0x82);
+        gb.mmu_write8(LY, 0);
+        gb.mmu_write8(OBP0, 0xE4);
+        gb.mmu_write8(OBP1, 0x1B);
+        gb.blank_frame();
+        gb
+    }
+
+    fn set_sprite(gb: &mut GBEmulator, index: u16, y: u8, x: u8, tile: u8, flags: u8) {
+        let addr = (OAM + index * 4) as usize;
+        gb.mem[addr..addr + 4].copy_from_slice(&[y, x, tile, flags]);
+    }
+
+    /* Every pixel of one row of a tile in colour */
+    fn fill_tile_row(gb: &mut GBEmulator, tile: u8, row: u16, color: u8) {
+        let addr = 0x8000 + tile as usize * 16 + row as usize * 2;
+        gb.mem[addr] = if color & 1 != 0 { 0xFF } else { 0x00 };
+        gb.mem[addr + 1] = if color & 2 != 0 { 0xFF } else { 0x00 };
+    }
+
+    fn color(gb: &GBEmulator, x: usize) -> [u8; 4] {
+        let mut color = [0; 4];
+        color.copy_from_slice(&gb.framebuffer[x * 4..x * 4 + 4]);
+        color
+    }
+
+    #[test]
+    fn ten_sprites_per_line_in_oam_order() {
+        let mut gb = sprite_gb();
+        fill_tile_row(&mut gb, 1, 0, 3);
+        /* Not on the line, so it doesn't count */
+        set_sprite(&mut gb, 0, 0, 8, 1, 0);
+        /* Later in OAM further left, the last two are dropped even with
+         * the lowest X */
+        for index in 1..=12 {
+            set_sprite(&mut gb, index, 16, 8 + (12 - index as u8) * 8, 1, 0);
+        }
+        gb.draw_scanline();
+
+        for x in 0..16 {
+            assert_eq!(color(&gb, x), LIGHTEST, "pixel {}", x);
+        }
+        for x in 16..96 {
+            assert_eq!(color(&gb, x), DARKEST, "pixel {}", x);
+        }
+        assert_eq!(color(&gb, 96), LIGHTEST);
+    }
+
+    #[test]
+    fn lower_x_wins() {
+        let mut gb = sprite_gb();
+        fill_tile_row(&mut gb, 1, 0, 1);
+        fill_tile_row(&mut gb, 2, 0, 2);
+        set_sprite(&mut gb, 0, 16, 12, 1, 0);
+        set_sprite(&mut gb, 1, 16, 8, 2, 0);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 3), DARK);
+        assert_eq!(color(&gb, 4), DARK);
+        assert_eq!(color(&gb, 8), LIGHT);
+
+        /* Equal X goes to the first in OAM */
+        set_sprite(&mut gb, 0, 16, 8, 1, 0);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 4), LIGHT);
+    }
+
+    #[test]
+    fn colour_0_is_transparent() {
+        let mut gb = sprite_gb();
+        /* Colour 1 on the left half, 0 on the right */
+        gb.mem[0x8010] = 0xF0;
+        fill_tile_row(&mut gb, 2, 0, 2);
+        set_sprite(&mut gb, 0, 16, 8, 1, 0);
+        set_sprite(&mut gb, 1, 16, 8, 2, 0);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 3), LIGHT);
+        assert_eq!(color(&gb, 4), DARK);
+
+        /* With nothing below the background shows */
+        set_sprite(&mut gb, 1, 0, 0, 0, 0);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 4), LIGHTEST);
+    }
+
+    #[test]
+    fn behind_background() {
+        let mut gb = sprite_gb();
+        fill_tile_row(&mut gb, 1, 0, 3);
+        set_sprite(&mut gb, 0, 16, 8, 1, OBJ_BEHIND_BG);
+        set_sprite(&mut gb, 1, 16, 16, 1, 0);
+
+        /* Only shows through background colour 0 */
+        let mut bg_values = [0; 160];
+        bg_values[1] = 1;
+        bg_values[2] = 2;
+        bg_values[3] = 3;
+        bg_values[8] = 3;
+        gb.draw_sprites(0, &bg_values);
+        assert_eq!(color(&gb, 0), DARKEST);
+        assert_eq!(color(&gb, 1), LIGHTEST);
+        assert_eq!(color(&gb, 2), LIGHTEST);
+        assert_eq!(color(&gb, 3), LIGHTEST);
+        assert_eq!(color(&gb, 8), DARKEST);
+
+        /* A hidden sprite still wins over one with a higher X */
+        set_sprite(&mut gb, 1, 16, 10, 1, 0);
+        gb.blank_frame();
+        gb.draw_sprites(0, &bg_values);
+        assert_eq!(color(&gb, 3), LIGHTEST);
+        assert_eq!(color(&gb, 8), DARKEST);
+    }
+
+    #[test]
+    fn flips() {
+        let mut gb = sprite_gb();
+        /* Colour 1 top left, colour 2 bottom right */
+        gb.mem[0x8010] = 0x80;
+        gb.mem[0x801F] = 0x01;
+
+        let cases = [
+            (0, [LIGHT, LIGHTEST]),
+            (OBJ_X_FLIP, [LIGHTEST, LIGHT]),
+            (OBJ_Y_FLIP, [LIGHTEST, DARK]),
+            (OBJ_X_FLIP | OBJ_Y_FLIP, [DARK, LIGHTEST]),
+        ];
+        for &(flags, expected) in cases.iter() {
+            set_sprite(&mut gb, 0, 16, 8, 1, flags);
+            gb.draw_scanline();
+            assert_eq!([color(&gb, 0), color(&gb, 7)], expected, "flags {:#04X}", flags);
+        }
+    }
+
+    #[test]
+    fn tall_sprites() {
+        let mut gb = sprite_gb();
+        fill_tile_row(&mut gb, 2, 0, 1);
+        fill_tile_row(&mut gb, 3, 0, 2);
+
+        /* 8x8, tile 3 as given */
+        set_sprite(&mut gb, 0, 16, 8, 3, 0);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 0), DARK);
+
+        /* 8x16, tiles 2 then 3 whatever bit 0 says */
+        gb.mmu_write8(LCDC, 0x86);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 0), LIGHT);
+        set_sprite(&mut gb, 0, 8, 8, 3, 0);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 0), DARK);
+        set_sprite(&mut gb, 0, 8, 8, 2, 0);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 0), DARK);
+    }
+
+    #[test]
+    fn palettes() {
+        let mut gb = sprite_gb();
+        fill_tile_row(&mut gb, 1, 0, 1);
+        set_sprite(&mut gb, 0, 16, 8, 1, 0);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 0), LIGHT);
+
+        set_sprite(&mut gb, 0, 16, 8, 1, OBJ_PALETTE);
+        gb.draw_scanline();
+        assert_eq!(color(&gb, 0), DARK);
+    }
+
+    /* Colour numbers of line 0 of the background, map at 0x9800 */
+    fn bg_line(gb: &mut GBEmulator, lcdc: u8) -> [u8; 160] {
+        gb.mmu_write8(LCDC, lcdc);
+        let mut bg_values = [0; 160];
+        gb.draw_background(0, &mut bg_values);
+        bg_values
+    }
+
+    #[test]
+    fn tile_addressing() {
+        let mut gb = GBEmulator::blank();
+        gb.mem[0x9800..0x9803].copy_from_slice(&[0x80, 0x7F, 0x00]);
+        /* Row 0 of each tile, in a colour of its own */
+        gb.mem[0x8000] = 0xFF;
+        gb.mem[0x87F0..0x87F2].copy_from_slice(&[0xFF, 0xFF]);
+        gb.mem[0x8800] = 0xFF;
+        gb.mem[0x97F1] = 0xFF;
+        gb.mem[0x9000..0x9002].copy_from_slice(&[0xFF, 0xFF]);
+
+        /* Signed from 0x9000, 0x80 is 0x8800 and 0x7F is 0x97F0 */
+        let bg_values = bg_line(&mut gb, 0x81);
+        assert_eq!(bg_values[0], 1);
+        assert_eq!(bg_values[8], 2);
+        assert_eq!(bg_values[16], 3);
+
+        /* Unsigned from 0x8000, 0x80 is still 0x8800 */
+        let bg_values = bg_line(&mut gb, 0x91);
+        assert_eq!(bg_values[0], 1);
+        assert_eq!(bg_values[8], 3);
+        assert_eq!(bg_values[16], 1);
+    }
+
+    #[test]
+    fn tile_pixels() {
+        let mut gb = GBEmulator::blank();
+        /* Bit 7 is the leftmost pixel, byte 1 the high bit of the colour */
+        gb.mem[0x8000..0x8004].copy_from_slice(&[0xA0, 0x60, 0x01, 0x80]);
+
+        let bg_values = bg_line(&mut gb, 0x91);
+        assert_eq!(&bg_values[0..8], &[1, 2, 3, 0, 0, 0, 0, 0]);
+
+        /* Rows are picked by the scrolled line */
+        gb.mmu_write8(SCY, 1);
+        let bg_values = bg_line(&mut gb, 0x91);
+        assert_eq!(&bg_values[0..8], &[2, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    /* Window on from line 0 at the left edge, with unsigned tiles.  Tile 1
+     * is colour 3 and tile 2 colour 1, on row 0 only. */
+    fn window_gb(lcdc: u8) -> GBEmulator {
+        let mut gb = GBEmulator::blank();
+        gb.mmu_write8(LCDC, 0xB1 | lcdc);
+        gb.mmu_write8(WY, 0);
+        gb.mmu_write8(WX, 7);
+        fill_tile_row(&mut gb, 1, 0, 3);
+        fill_tile_row(&mut gb, 2, 0, 1);
+        gb
+    }
+
+    fn line(gb: &mut GBEmulator, ly: u8) -> [u8; 160] {
+        let mut bg_values = [0; 160];
+        gb.draw_background(ly, &mut bg_values);
+        bg_values
+    }
+
+    #[test]
+    fn window_x() {
+        let mut gb = window_gb(0x40);
+        gb.mem[0x9C00] = 1;
+
+        /* WX is 7 past the screen X */
+        let bg_values = line(&mut gb, 0);
+        assert_eq!(&bg_values[0..9], &[3, 3, 3, 3, 3, 3, 3, 3, 0]);
+
+        gb.window_line = 0;
+        gb.mmu_write8(WX, 8);
+        let bg_values = line(&mut gb, 0);
+        assert_eq!(&bg_values[0..9], &[0, 3, 3, 3, 3, 3, 3, 3, 3]);
+
+        /* 166 leaves one column showing, 167 none */
+        gb.window_line = 0;
+        gb.mmu_write8(WX, 166);
+        assert_eq!(line(&mut gb, 0)[159], 3);
+        assert_eq!(gb.window_line, 1);
+        gb.window_line = 0;
+        gb.mmu_write8(WX, 167);
+        assert_eq!(line(&mut gb, 0)[159], 0);
+        assert_eq!(gb.window_line, 0);
+    }
+
+    #[test]
+    fn window_line_counter() {
+        let mut gb = window_gb(0x40);
+        gb.mem[0x9C00] = 1;
+        /* Colour 3 on window row 2 only */
+        fill_tile_row(&mut gb, 1, 0, 0);
+        fill_tile_row(&mut gb, 1, 2, 3);
+        gb.mmu_write8(WY, 2);
+
+        /* Above WY it doesn't count */
+        line(&mut gb, 0);
+        line(&mut gb, 1);
+        assert_eq!(gb.window_line, 0);
+        line(&mut gb, 2);
+        line(&mut gb, 3);
+        assert_eq!(gb.window_line, 2);
+
+        /* Nor while moved off screen or switched off */
+        gb.mmu_write8(WX, 167);
+        line(&mut gb, 4);
+        gb.mmu_write8(WX, 7);
+        gb.mmu_write8(LCDC, 0x91 | 0x40);
+        line(&mut gb, 5);
+        assert_eq!(gb.window_line, 2);
+
+        /* It picks up on the row after the last one drawn */
+        gb.mmu_write8(LCDC, 0xB1 | 0x40);
+        assert_eq!(line(&mut gb, 6)[0], 3);
+        assert_eq!(gb.window_line, 3);
+    }
+
+    #[test]
+    fn window_line_reset() {
+        let mut gb = window_gb(0);
+
+        /* At the end of the last VBlank line */
+        gb.mmu_write8(LY, VBLANK_SCANLINE_MAX);
+        gb.gpu_frame_cycles = 4;
+        gb.window_line = 5;
+        gb.gpu_run(4);
+        assert_eq!(gb.mmu_read8(LY), 0);
+        assert_eq!(gb.window_line, 0);
+
+        /* And with the LCD off */
+        gb.window_line = 5;
+        gb.mmu_write8(LCDC, 0x00);
+        gb.gpu_run(4);
+        assert_eq!(gb.window_line, 0);
+    }
+
+    #[test]
+    fn window_map() {
+        /* BG on the left half, the window on the right */
+        for &(lcdc, bg, window) in [(0x40, 1, 3), (0x08, 3, 1), (0x48, 3, 3), (0x00, 1, 1)].iter() {
+            let mut gb = window_gb(lcdc);
+            gb.mem[0x9800] = 2;
+            gb.mem[0x9C00] = 1;
+            gb.mmu_write8(WX, 87);
+            let bg_values = line(&mut gb, 0);
+            assert_eq!((bg_values[0], bg_values[80]), (bg, window), "LCDC {:#04X}", lcdc);
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,36 +1,109 @@
-use super::GBEmulator;
+use super::{Bus, Cpu, CpuState, GBEmulator};
 
-const IME: u16    = 0xFF40;
 const IF: u16     = 0xFF0F;
 const IE: u16     = 0xFFFF;
 
+/* Interrupt master enable */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ime {
+    Disabled,
+    /* EI was executed, IME is set once the next instruction completes */
+    Pending,
+    Enabled,
+}
+
+/* Interrupt sources, in IF/IE bit order which is also their priority */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    const PRIORITY: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::Stat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    fn mask(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 1 << 0,
+            Interrupt::Stat   => 1 << 1,
+            Interrupt::Timer  => 1 << 2,
+            Interrupt::Serial => 1 << 3,
+            Interrupt::Joypad => 1 << 4,
+        }
+    }
+
+    fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::Stat   => 0x48,
+            Interrupt::Timer  => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
+
 impl GBEmulator {
-    pub fn request_irq(&mut self, irq: u8) {
-        let value = self.mmu_read8(IF) | (1 << irq);
-        self.mmu_write8(IF, value);
+    pub fn request_irq(&mut self, irq: Interrupt) {
+        self.mem[IF as usize] |= irq.mask();
     }
 
-    pub fn handle_irqs(&mut self) {
-        if !self.interrupts_en {
-            return;
+    /* Only the five interrupt bits exist, the rest read back as 1 */
+    pub fn irq_flags_read(&self) -> u8 {
+        self.mem[IF as usize] | 0xE0
+    }
+
+    pub fn irq_flags_write(&mut self, value: u8) {
+        self.mem[IF as usize] = value & 0x1F;
+    }
+}
+
+/* Interrupts that are both requested and enabled.  IF and IE are read over
+ * the bus, so this works the same on any Bus */
+pub fn pending_irqs<B: Bus>(bus: &mut B) -> u8 {
+    bus.read8(IF) & bus.read8(IE) & 0x1F
+}
+
+impl Cpu {
+    /* Dispatch the highest priority pending interrupt, returning whether
+     * one was dispatched */
+    pub fn handle_irqs<B: Bus>(&mut self, bus: &mut B) -> bool {
+        /* Nothing gets a locked up CPU going again */
+        if self.ime != Ime::Enabled || self.state == CpuState::Locked {
+            return false;
         }
-        let irq_flags = self.mmu_read8(IF);
-        let irqs = irq_flags & self.mmu_read8(IE);
-
-        for bit in 0..8 {
-            if irqs & (1 << bit) != 0 {
-                self.stack_push(self.regs.pc);
-                self.regs.pc = match bit {
-                    0 => 0x40, /* V-Blank */
-                    1 => 0x48, /* LCD-STATE */
-                    2 => 0x50, /* Timer */
-                    3 => 0x60, /* Joypad */
-                    _ => panic!("Invalid interrupt {} fired", bit),
-                };
-                self.interrupts_en = false;
-                self.mmu_write8(IF, irq_flags & !(irq_flags | (1 << bit)));
-                break;
-            }
+
+        let irqs = pending_irqs(bus);
+        let irq = match Interrupt::PRIORITY.iter().find(|irq| irqs & irq.mask() != 0) {
+            Some(irq) => *irq,
+            None => return false,
+        };
+
+        /* Leaving HALT takes one more cycle */
+        if self.state == CpuState::Halted {
+            self.state = CpuState::Running;
+            self.tick(bus);
         }
+
+        /* Two wait states, the push and the jump */
+        self.ime = Ime::Disabled;
+        let flags = bus.read8(IF);
+        bus.write8(IF, flags & !irq.mask());
+        self.tick(bus);
+        self.tick(bus);
+        self.stack_push(bus, self.regs.pc);
+        self.regs.pc = irq.vector();
+        self.tick(bus);
+
+        true
     }
-}
\ No newline at end of file
+}
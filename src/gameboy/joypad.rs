@@ -0,0 +1,89 @@
+use super::GBEmulator;
+use super::interrupts::Interrupt;
+
+const P1: usize = 0xFF00;
+
+/* P1 select lines, these are active low */
+const SELECT_DIRECTIONS: u8 = 1 << 4;
+const SELECT_BUTTONS: u8    = 1 << 5;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Right,
+    Left,
+    Up,
+    Down,
+}
+
+impl Button {
+    /* Bit in GBEmulator::joypad, the buttons are in the low nibble and
+     * the directions in the high nibble, both in P1 line order */
+    fn mask(self) -> u8 {
+        match self {
+            Button::A      => 1 << 0,
+            Button::B      => 1 << 1,
+            Button::Select => 1 << 2,
+            Button::Start  => 1 << 3,
+            Button::Right  => 1 << 4,
+            Button::Left   => 1 << 5,
+            Button::Up     => 1 << 6,
+            Button::Down   => 1 << 7,
+        }
+    }
+}
+
+impl GBEmulator {
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let lines = self.joypad_lines();
+        if pressed {
+            self.joypad |= button.mask();
+        } else {
+            self.joypad &= !button.mask();
+        }
+        self.joypad_lines_changed(lines);
+    }
+
+    /* Tilt for carts with an accelerometer, in g along each axis.  Positive
+     * x tilts right and positive y tilts down. */
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cart.set_tilt(x, y);
+    }
+
+    pub fn joypad_read(&self) -> u8 {
+        0xC0 | (self.mem[P1] & 0x30) | self.joypad_lines()
+    }
+
+    /* Only the select lines are writable */
+    pub fn joypad_write(&mut self, value: u8) {
+        let lines = self.joypad_lines();
+        self.mem[P1] = value & 0x30;
+        self.joypad_lines_changed(lines);
+    }
+
+    /* P10-P13 as the CPU sees them, a pressed button on a selected
+     * line pulls it low */
+    fn joypad_lines(&self) -> u8 {
+        let select = self.mem[P1];
+        let mut pressed = 0;
+        if select & SELECT_DIRECTIONS == 0 {
+            pressed |= self.joypad >> 4;
+        }
+        if select & SELECT_BUTTONS == 0 {
+            pressed |= self.joypad & 0x0F;
+        }
+        !pressed & 0x0F
+    }
+
+    fn joypad_lines_changed(&mut self, old_lines: u8) {
+        /* Any line going from high to low raises the joypad
+         * interrupt and ends STOP mode */
+        if old_lines & !self.joypad_lines() != 0 {
+            self.request_irq(Interrupt::Joypad);
+            self.stopped = false;
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::GBEmulator;
+use super::{Bus, GBEmulator};
 
 impl GBEmulator {
     pub fn mmu_read8(&self, addr: u16) -> u8 {
@@ -9,16 +9,16 @@ impl GBEmulator {
                 if self.in_bios {
                     self.bios[addr]
                 } else {
-                    self.rom[addr]
+                    self.cart.read8(addr as u16)
                 }
             },
-            0x100    ..= 0x3FFF => self.rom[addr],
+            0x100    ..= 0x3FFF => self.cart.read8(addr as u16),
             /* Cartridge, selectable bank */
-            0x4000 ..= 0x7FFF => { self.rom[addr] },
+            0x4000 ..= 0x7FFF => { self.cart.read8(addr as u16) },
             /* 8 KiB VRAM, switchable bank 0/1 */
             0x8000 ..= 0x9FFF => { self.mem[addr] },
             /* 8 KiB External RAM, in catridge with switchable banks */
-            0xA000 ..= 0xBFFF => { self.mem[addr] },
+            0xA000 ..= 0xBFFF => { self.cart.read8(addr as u16) },
             /* 4 KiB Work RAM bank 0 */
             0xC000 ..= 0xCFFF => { self.mem[addr] },
             /* 4 KiB Work RAM bank 1 */
@@ -30,7 +30,12 @@ impl GBEmulator {
             /* Reserved, does nothing */
             0xFEA0 ..= 0xFEFF => { 0x0 },
             /* IO Ports */
-            0xFF00 ..= 0xFF7F => { self.mem[addr] },
+            0xFF00            => { self.joypad_read() },
+            0xFF01 ..= 0xFF03 => { self.mem[addr] },
+            0xFF04 ..= 0xFF07 => { self.timer_read(addr as u16) },
+            0xFF08 ..= 0xFF0E => { self.mem[addr] },
+            0xFF0F            => { self.irq_flags_read() },
+            0xFF10 ..= 0xFF7F => { self.mem[addr] },
             /* High RAM (HRAM) */
             0xFF80 ..= 0xFFFE => { self.mem[addr] },
             /* Interrupt Enable Register */
@@ -43,13 +48,13 @@ impl GBEmulator {
         let addr = addr as usize;
         match addr {
             /* Cartridge, fixed bank 00 */
-            0x0    ..= 0x3FFF => {  },
+            0x0    ..= 0x3FFF => { self.cart.write8(addr as u16, value) },
             /* Cartridge, selectable bank */
-            0x4000 ..= 0x7FFF => {  },
+            0x4000 ..= 0x7FFF => { self.cart_register_write(addr as u16, value) },
             /* 8 KiB VRAM, switchable bank 0/1 */
             0x8000 ..= 0x9FFF => { self.mem[addr] = value },
             /* 8 KiB External RAM, in catridge with switchable banks */
-            0xA000 ..= 0xBFFF => { self.mem[addr] = value },
+            0xA000 ..= 0xBFFF => { self.cart.write8(addr as u16, value) },
             /* 4 KiB Work RAM bank 0 */
             0xC000 ..= 0xCFFF => { self.mem[addr] = value },
             /* 4 KiB Work RAM bank 1 */
@@ -61,10 +66,16 @@ impl GBEmulator {
             /* Reserved, does nothing */
             0xFEA0 ..= 0xFEFF => {  },
             /* IO Ports */
-            0xFF00 ..= 0xFF03 => { self.mem[addr] = value },
-            0xFF04            => { self.mem[addr] = 0 },
-            0xFF05 ..= 0xFF45 => { self.mem[addr] = value },
-            0xFF46            => { self.dma_transfer(value) },
+            0xFF00            => { self.joypad_write(value) },
+            0xFF01 ..= 0xFF03 => { self.mem[addr] = value },
+            0xFF04 ..= 0xFF07 => { self.timer_write(addr as u16, value) },
+            0xFF08 ..= 0xFF0E => { self.mem[addr] = value },
+            0xFF0F            => { self.irq_flags_write(value) },
+            0xFF10 ..= 0xFF40 => { self.mem[addr] = value },
+            /* STAT, the mode and coincidence bits are read only */
+            0xFF41            => { self.mem[addr] = (value & 0x78) | (self.mem[addr] & 0x87) },
+            0xFF42 ..= 0xFF45 => { self.mem[addr] = value },
+            0xFF46            => { self.mem[addr] = value; self.dma_start(value) },
             0xFF47 ..= 0xFF4F => { self.mem[addr] = value },
             0xFF50            => { self.in_bios = false },
             0xFF51 ..= 0xFF7F => { self.mem[addr] = value },
@@ -72,35 +83,142 @@ impl GBEmulator {
             0xFF80 ..= 0xFFFE => { self.mem[addr] = value },
             /* Interrupt Enable Register */
             0xFFFF            => { self.mem[addr] = value },
-            _                 => panic!("Tried to access memory outside the MMU: {:#06X} at pc {:#06X}", addr, self.regs.pc),
+            _                 => panic!("Tried to access memory outside the MMU: {:#06X}", addr),
         };
     }
 
-    fn dma_transfer(&mut self, value: u8) {
-        let addr = (value as u16) << 8;
-        for offset in 0..0xA0 {
-            self.mmu_write8(0xFE00 + offset, self.mmu_read8(addr + offset));
+    /* The PPU has VRAM to itself while drawing (mode 3), and OAM while
+     * searching it or drawing (modes 2 and 3) */
+    fn cpu_can_access(&self, addr: u16) -> bool {
+        if !self.restrict_vram_oam {
+            return true;
+        }
+        match addr {
+            0x8000 ..= 0x9FFF => self.ppu_mode() != 3,
+            0xFE00 ..= 0xFE9F => self.ppu_mode() < 2,
+            _ => true,
+        }
+    }
+
+    /* Rumble carts switch the motor from the RAM bank register */
+    fn cart_register_write(&mut self, addr: u16, value: u8) {
+        let rumble = self.cart.rumble();
+        self.cart.write8(addr, value);
+        if self.cart.rumble() != rumble {
+            if let Some(callback) = &mut self.rumble_callback {
+                callback(!rumble);
+            }
+        }
+    }
+}
+
+/* The CPU's side of the MMU.  A read locked out by the PPU gets 0xFF, one
+ * that conflicts with OAM DMA gets what the DMA put on the bus, and locked
+ * out writes are dropped. */
+impl Bus for GBEmulator {
+    fn read8(&mut self, addr: u16) -> u8 {
+        if let Some(value) = self.dma_bus_conflict(addr) {
+            return value;
+        }
+        if !self.cpu_can_access(addr) {
+            return 0xFF;
+        }
+        self.mmu_read8(addr)
+    }
+
+    fn write8(&mut self, addr: u16, value: u8) {
+        if self.dma_bus_conflict(addr).is_none() && self.cpu_can_access(addr) {
+            self.mmu_write8(addr, value);
+        }
+    }
+
+    /* In cycle accurate mode the rest of the system is clocked right away,
+     * so a bus access that follows sees the PPU, timers and DMA as they are
+     * at that point in the instruction.  Otherwise step() catches them up
+     * once the instruction is done. */
+    fn tick(&mut self) {
+        if self.cycle_accurate {
+            self.gpu_run(4);
+            self.timers_run(4);
+            self.dma_run(4);
         }
     }
 
-    pub fn mmu_read16(&self, addr: u16) -> u16 {
-        ((self.mmu_read8(addr + 1) as u16) << 8) | (self.mmu_read8(addr) as u16)
+    /* The LCD goes blank and DIV is reset */
+    fn stop(&mut self) {
+        self.stopped = true;
+        self.mmu_write8(0xFF04, 0);
+        self.blank_frame();
     }
 
-    pub fn mmu_write16(&mut self, addr: u16, value: u16) {
-        self.mmu_write8(addr, value as u8);
-        self.mmu_write8(addr + 1, (value >> 8) as u8);
+    fn stopped(&self) -> bool {
+        self.stopped
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Forces the PPU mode the CPU sees, with the LCD on */
+    fn set_mode(gb: &mut GBEmulator, mode: u8) {
+        gb.mem[0xFF40] |= 0x80;
+        gb.mem[0xFF41] = (gb.mem[0xFF41] & !0x3) | mode;
+    }
+
+    #[test]
+    fn vram_oam_locked_by_mode() {
+        let mut gb = GBEmulator::blank();
+        /* (mode, VRAM open, OAM open) */
+        for &(mode, vram, oam) in [(0, true, true), (1, true, true), (2, true, false), (3, false, false)].iter() {
+            set_mode(&mut gb, mode);
+            assert_eq!(gb.cpu_can_access(0x8000), vram, "VRAM in mode {}", mode);
+            assert_eq!(gb.cpu_can_access(0x9FFF), vram, "VRAM in mode {}", mode);
+            assert_eq!(gb.cpu_can_access(0xFE00), oam, "OAM in mode {}", mode);
+            assert_eq!(gb.cpu_can_access(0xFE9F), oam, "OAM in mode {}", mode);
+            assert!(gb.cpu_can_access(0xC000));
+            assert!(gb.cpu_can_access(0xFEA0));
+        }
 
-    pub fn pc_read8(&mut self) -> u8 {
-        let val = self.mmu_read8(self.regs.pc);
-        self.regs.pc += 1;
-        val
+        /* With the LCD off the mode bits are ignored */
+        gb.mem[0xFF40] &= !0x80;
+        assert!(gb.cpu_can_access(0x8000));
+        assert!(gb.cpu_can_access(0xFE00));
     }
 
-    pub fn pc_read16(&mut self) -> u16 {
-        let val = self.mmu_read16(self.regs.pc);
-        self.regs.pc += 2;
-        val
+    #[test]
+    fn locked_reads_and_writes() {
+        let mut gb = GBEmulator::blank();
+        gb.mem[0x8000] = 0x12;
+        gb.mem[0xFE00] = 0x34;
+
+        set_mode(&mut gb, 3);
+        assert_eq!(Bus::read8(&mut gb, 0x8000), 0xFF);
+        assert_eq!(Bus::read8(&mut gb, 0xFE00), 0xFF);
+        Bus::write8(&mut gb, 0x8000, 0x56);
+        Bus::write8(&mut gb, 0xFE00, 0x78);
+        assert_eq!(gb.mem[0x8000], 0x12);
+        assert_eq!(gb.mem[0xFE00], 0x34);
+
+        /* The PPU itself still sees through the lock */
+        assert_eq!(gb.mmu_read8(0x8000), 0x12);
+
+        set_mode(&mut gb, 0);
+        Bus::write8(&mut gb, 0x8000, 0x56);
+        Bus::write8(&mut gb, 0xFE00, 0x78);
+        assert_eq!(Bus::read8(&mut gb, 0x8000), 0x56);
+        assert_eq!(Bus::read8(&mut gb, 0xFE00), 0x78);
+    }
+
+    #[test]
+    fn restriction_off() {
+        let mut gb = GBEmulator::blank();
+        gb.restrict_vram_oam = false;
+        set_mode(&mut gb, 3);
+        assert!(gb.cpu_can_access(0x8000));
+        assert!(gb.cpu_can_access(0xFE00));
+
+        Bus::write8(&mut gb, 0x8000, 0x56);
+        assert_eq!(Bus::read8(&mut gb, 0x8000), 0x56);
     }
-}
\ No newline at end of file
+}
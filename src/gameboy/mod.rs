@@ -1,14 +1,32 @@
 pub use registers::{Registers};
+pub use bus::{Bus, RamBus};
+pub use cpu::{Cpu, CpuState};
+pub use interrupts::Ime;
+pub use disasm::disassemble_rom;
+pub use joypad::Button;
+pub use error::EmulatorError;
+pub use cartridge::Cartridge;
 
 //pub use self::gameboy::
 
+mod bus;
+mod cartridge;
 mod cpu;
+mod decoder;
+mod disasm;
+mod dma;
+mod error;
 mod registers;
 mod mmu;
 mod gpu;
 mod interrupts;
+mod joypad;
 mod timers;
 
+const CYCLES_PER_FRAME: u32 = 70224;
+
+const BIOS_SIZE: usize = 0x100;
+
 /* 
  * https://gbdev.io/gb-opcodes/optables/
  * https://gekkio.fi/files/gb-docs/gbctr.pdf
@@ -23,41 +41,134 @@ mod timers;
 
 pub struct GBEmulator {
     mem: [u8; 0x10000],
-    pub regs: Registers,
+    pub cpu: Cpu,
     in_bios: bool,
     bios: Vec<u8>,
-    rom: Vec<u8>,
-    debug: bool,
+    pub cart: Cartridge,
+    /* Set by STOP, the LCD and timers are halted until a joypad
+     * line goes low */
     stopped: bool,
-    halted: bool,
-    interrupts_en: bool,
+    joypad: u8, /* Pressed buttons, see joypad::Button */
+    dma: Option<dma::OamDma>,
+    rumble_callback: Option<Box<dyn FnMut(bool)>>,
     pub frame_hz: u32,
+    /* Clock the PPU and timers on every CPU M-cycle rather than once
+     * per instruction */
+    pub cycle_accurate: bool,
+    /* Block CPU access to VRAM and OAM while the PPU is using them, as on
+     * hardware.  Turning it off lets through the accesses that only work
+     * in emulators. */
+    pub restrict_vram_oam: bool,
+    step_cycles: u32,
     gpu_frame_cycles: u32,
+    /* Window line to draw next, only counts lines the window was on */
+    window_line: u8,
+    /* System counter, DIV is the upper byte */
+    divider: u16,
+    /* TIMA overflowed last M-cycle and reloads from TMA on this one */
+    tima_overflow: bool,
+    /* TIMA was reloaded from TMA in this M-cycle */
+    tima_reloading: bool,
     pub framebuffer: [u8; 160*144*4], /* RGB for each pixel */
 }
 
 impl GBEmulator {
-    pub fn new(bios: Vec<u8>, rom: Vec<u8>)  -> GBEmulator {
+    pub fn new(bios: Vec<u8>, cart: Cartridge) -> Result<GBEmulator, EmulatorError> {
+        if bios.len() != BIOS_SIZE {
+            return Err(EmulatorError::InvalidBootRom(bios.len()));
+        }
+
         let mut gb = GBEmulator {
             mem: [0; 0x10000],
-            regs: Registers::default(),
+            cpu: Cpu::new(),
             in_bios: true,
             bios,
-            rom,
-            debug: false,
+            cart,
             stopped: false,
-            halted: false,
-            interrupts_en: false,
+            joypad: 0,
+            dma: None,
+            rumble_callback: None,
             frame_hz: 60,
+            cycle_accurate: true,
+            restrict_vram_oam: true,
+            step_cycles: 0,
             gpu_frame_cycles: 456,
+            window_line: 0,
+            divider: 0,
+            tima_overflow: false,
+            tima_reloading: false,
             framebuffer: [0; 160*144*4],
         };
 
-        gb.mmu_write8(0xFF41, 0x84); /* STAT */
+        gb.mmu_write8(0xFF00, 0x30); /* P1 */
+        gb.mem[0xFF41] = 0x84; /* STAT, mode bits are read only */
         gb.mmu_write8(0xFF47, 0xFC); /* BGP */
         gb.mmu_write8(0xFF48, 0xFF); /* OBP0 */
         gb.mmu_write8(0xFF49, 0xFF); /* OBG1 */
 
-        gb
+        Ok(gb)
+    }
+
+    pub fn from_files<P: AsRef<std::path::Path>>(bios: P, rom: P) -> Result<GBEmulator, EmulatorError> {
+        GBEmulator::new(std::fs::read(bios)?, Cartridge::new(std::fs::read(rom)?)?)
+    }
+
+    /* Called with the new motor state whenever a rumble cart turns it
+     * on or off.  Games pulse it to vary the strength. */
+    pub fn on_rumble<F: FnMut(bool) + 'static>(&mut self, callback: F) {
+        self.rumble_callback = Some(Box::new(callback));
+    }
+
+    /* Run one instruction, or an interrupt dispatch if one is due, and
+     * clock the rest of the system to match.  Returns the clocks taken,
+     * or CpuLocked once an illegal opcode has hung the CPU. */
+    pub fn step(&mut self) -> Result<u32, EmulatorError> {
+        /* The CPU is taken out while it runs, so that the rest of the
+         * emulator can be its bus */
+        let mut cpu = std::mem::take(&mut self.cpu);
+        self.step_cycles = cpu.step(self);
+        self.cpu = cpu;
+
+        if !self.cycle_accurate {
+            self.gpu_run(self.step_cycles);
+            self.timers_run(self.step_cycles);
+            self.dma_run(self.step_cycles);
+        }
+        self.check_locked()?;
+        Ok(self.step_cycles)
+    }
+
+    /* Run for one frame worth of clocks.  A locked up CPU stays locked, so
+     * it is only reported at the end and the rest of the system carries on
+     * regardless. */
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        let mut framecycles = 0;
+        while framecycles < CYCLES_PER_FRAME {
+            let _ = self.step();
+            framecycles += self.step_cycles;
+        }
+        self.check_locked()
+    }
+
+    /* The CPU records the illegal opcode as it locks up, PC may not
+     * have moved past it after the HALT bug */
+    fn check_locked(&self) -> Result<(), EmulatorError> {
+        match self.cpu.locked_on {
+            Some((opcode, addr)) if self.cpu.state == CpuState::Locked => {
+                Err(EmulatorError::CpuLocked { opcode, addr })
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl GBEmulator {
+    /* Blank boot ROM and cartridge, for unit tests */
+    pub fn blank() -> GBEmulator {
+        let mut rom = vec![0; 0x8000];
+        /* Header checksum of an all zero header */
+        rom[0x14D] = 0xE7;
+        GBEmulator::new(vec![0; 0x100], Cartridge::new(rom).unwrap()).unwrap()
     }
 }
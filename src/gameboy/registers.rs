@@ -38,6 +38,23 @@ impl std::convert::From<u8> for FlagsReg {
     }
 }
 
+impl Default for Registers {
+    fn default() -> Registers {
+        Registers {
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            sp: 0,
+            flags: FlagsReg::from(0x00),
+            pc: 0,
+        }
+    }
+}
+
 impl Registers {
     pub fn default_no_bios() -> Registers {
         Registers {
@@ -53,20 +70,6 @@ impl Registers {
             pc: 0x100,
         }
     }
-    pub fn default() -> Registers {
-        Registers {
-            a: 0,
-            b: 0,
-            c: 0,
-            d: 0,
-            e: 0,
-            h: 0,
-            l: 0,
-            sp: 0,
-            flags: FlagsReg::from(0x00),
-            pc: 0,
-        }
-    }
 
     pub fn get_hl(&self) -> u16 {
         ((self.h as u16) << 8) | (self.l as u16)
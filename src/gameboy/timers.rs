@@ -1,20 +1,214 @@
 use super::GBEmulator;
+use super::interrupts::Interrupt;
 
-const DIV: u16  = 0xFF03;
+const DIV: u16  = 0xFF04;
 const TIMA: u16 = 0xFF05;
-const TIM: u16  = 0xFF06;
+const TMA: u16  = 0xFF06;
 const TAC: u16  = 0xFF07;
 
+const TAC_ENABLE: u8 = 1 << 2;
+
+/* Divider bit that clocks TIMA for each TAC clock select, 4096, 262144,
+ * 65536 and 16384 Hz */
+const TAC_DIVIDER_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+
+/* https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+ *
+ * There is just one counter, the 16-bit divider that counts every clock.
+ * TIMA is clocked by a falling edge detector on the divider bit selected
+ * in TAC, ANDed with the TAC enable bit.  That gives the glitches below,
+ * as checked by the mooneye acceptance/timer ROMs:
+ *
+ * - Writing DIV while the selected bit is set is a falling edge, so TIMA
+ *   counts early (div_write, rapid_toggle)
+ * - Writing TAC counts TIMA if it takes the detector input from high to
+ *   low, by disabling the timer or selecting a bit that is clear
+ *   (tim00_div_trigger and friends)
+ * - TIMA reads 0 for the M-cycle after it overflows.  Writing TIMA in that
+ *   cycle cancels the reload and the interrupt (tima_reload)
+ * - In the following M-cycle TIMA is loaded from TMA.  Writing TIMA then
+ *   is ignored, and writing TMA loads the new value into TIMA as well
+ *   (tima_write_reloading, tma_write_reloading)
+ */
 impl GBEmulator {
     pub fn timers_run(&mut self, cycles: u32) {
-        /* DIV always counts at 16khz
-         * This is the 4MHz/256 
-         * */
-        let mut div = self.mmu_read8(DIV);
-        div = div.wrapping_add(cycles as u8);
-        self.mmu_write8(DIV, div);
+        /* The divider is halted along with the CPU in STOP mode */
+        if self.stopped {
+            return;
+        }
+
+        for _ in 0..cycles / 4 {
+            /* An overflow leaves TIMA at 0 for one M-cycle, then it is
+             * reloaded and the interrupt raised */
+            self.tima_reloading = false;
+            if self.tima_overflow {
+                self.tima_overflow = false;
+                self.tima_reloading = true;
+                self.mem[TIMA as usize] = self.mem[TMA as usize];
+                self.request_irq(Interrupt::Timer);
+            }
+
+            let signal = self.timer_signal();
+            self.divider = self.divider.wrapping_add(4);
+            if signal && !self.timer_signal() {
+                self.tima_increment();
+            }
+        }
+    }
+
+    pub fn timer_read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV => (self.divider >> 8) as u8,
+            /* Only the low 3 bits of TAC exist */
+            TAC => self.mem[TAC as usize] | 0xF8,
+            _ => self.mem[addr as usize],
+        }
+    }
+
+    pub fn timer_write(&mut self, addr: u16, value: u8) {
+        let signal = self.timer_signal();
+        match addr {
+            /* Any write resets the whole divider */
+            DIV => self.divider = 0,
+            TAC => self.mem[TAC as usize] = value & 0x07,
+            TIMA if self.tima_reloading => {},
+            TIMA => {
+                self.tima_overflow = false;
+                self.mem[TIMA as usize] = value;
+            },
+            _ => {
+                self.mem[TMA as usize] = value;
+                if self.tima_reloading {
+                    self.mem[TIMA as usize] = value;
+                }
+            },
+        }
+
+        if signal && !self.timer_signal() {
+            self.tima_increment();
+        }
+    }
+
+    /* Input to the falling edge detector */
+    fn timer_signal(&self) -> bool {
+        let tac = self.mem[TAC as usize];
+        tac & TAC_ENABLE != 0 && self.divider & TAC_DIVIDER_BITS[(tac & 0x03) as usize] != 0
+    }
+
+    fn tima_increment(&mut self) {
+        let (tima, overflow) = self.mem[TIMA as usize].overflowing_add(1);
+        self.mem[TIMA as usize] = tima;
+        self.tima_overflow = overflow;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Cartridge;
+
+    const IF: usize = 0xFF0F;
+    const TIMER_IRQ: u8 = 1 << 2;
+
+    /* A blank ROM only lacks the header checksum */
+    fn gb() -> GBEmulator {
+        let mut rom = vec![0; 0x8000];
+        rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+        let cart = Cartridge::new(rom).unwrap();
+        GBEmulator::new(vec![0; 0x100], cart).unwrap()
+    }
+
+    /* TIMA counting every 16 clocks, on divider bit 3 */
+    fn gb_timer_on() -> GBEmulator {
+        let mut gb = gb();
+        gb.timer_write(TAC, TAC_ENABLE | 0x01);
+        gb
+    }
+
+    fn tima(gb: &GBEmulator) -> u8 {
+        gb.timer_read(TIMA)
+    }
+
+    /* Leaves TIMA just overflowed, with the reload due next M-cycle */
+    fn overflow(gb: &mut GBEmulator) {
+        gb.timer_write(TMA, 0x42);
+        gb.timer_write(TIMA, 0xFF);
+        gb.timers_run(16);
+        assert_eq!(tima(gb), 0x00);
+        assert_eq!(gb.mem[IF] & TIMER_IRQ, 0);
+    }
+
+    #[test]
+    fn div_write_counts_on_falling_edge() {
+        let mut gb = gb_timer_on();
+
+        /* Selected bit clear, no edge */
+        gb.timers_run(4);
+        gb.timer_write(DIV, 0);
+        assert_eq!(tima(&gb), 0);
+
+        gb.timers_run(8);
+        gb.timer_write(DIV, 0);
+        assert_eq!(tima(&gb), 1);
+        assert_eq!(gb.timer_read(DIV), 0);
+    }
+
+    #[test]
+    fn tac_write_counts_on_falling_edge() {
+        let mut gb = gb_timer_on();
+        gb.timers_run(8);
+
+        /* Selecting bit 5, which is clear */
+        gb.timer_write(TAC, TAC_ENABLE | 0x02);
+        assert_eq!(tima(&gb), 1);
+
+        /* Back to bit 3 is a rising edge, then disabling a falling one */
+        gb.timer_write(TAC, TAC_ENABLE | 0x01);
+        assert_eq!(tima(&gb), 1);
+        gb.timer_write(TAC, 0x01);
+        assert_eq!(tima(&gb), 2);
+    }
+
+    #[test]
+    fn tima_write_cancels_reload() {
+        let mut gb = gb_timer_on();
+        overflow(&mut gb);
+
+        gb.timer_write(TIMA, 0x10);
+        gb.timers_run(4);
+        assert_eq!(tima(&gb), 0x10);
+        assert_eq!(gb.mem[IF] & TIMER_IRQ, 0);
+    }
+
+    #[test]
+    fn tima_write_while_reloading_is_ignored() {
+        let mut gb = gb_timer_on();
+        overflow(&mut gb);
+        gb.timers_run(4);
+        assert_eq!(tima(&gb), 0x42);
+        assert_ne!(gb.mem[IF] & TIMER_IRQ, 0);
+
+        gb.timer_write(TIMA, 0x10);
+        assert_eq!(tima(&gb), 0x42);
+
+        /* The cycle after that it sticks */
+        gb.timers_run(4);
+        gb.timer_write(TIMA, 0x10);
+        assert_eq!(tima(&gb), 0x10);
+    }
+
+    #[test]
+    fn tma_write_while_reloading_loads_tima() {
+        let mut gb = gb_timer_on();
+        overflow(&mut gb);
+        gb.timers_run(4);
 
-        let tac = self.mmu_read8(TAC);
+        gb.timer_write(TMA, 0x99);
+        assert_eq!(tima(&gb), 0x99);
+        assert_eq!(gb.timer_read(TMA), 0x99);
 
+        gb.timers_run(4);
+        gb.timer_write(TMA, 0x55);
+        assert_eq!(tima(&gb), 0x99);
     }
-}
\ No newline at end of file
+}
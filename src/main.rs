@@ -1,5 +1,12 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use gilrs::{EventType, Gilrs};
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder};
 use pixels::SurfaceTexture;
-use log::error;
+use log::{error, info};
 use pixels::Pixels;
 use winit::dpi::LogicalSize;
 use winit::event::{Event, VirtualKeyCode};
@@ -7,44 +14,87 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
+use rusty_gbe::gameboy;
+
 /*mod cpu;
 mod gpu;*/
 
-mod gameboy;
-
 const SCREEN_WIDTH: u32 = 160;
 const SCREEN_HEIGHT: u32 = 144;
 
-fn main() {
-    let foo = "a,1,3,4,5";
-    let values: Vec<i32> = foo.split(",").filter_map(|x| x.parse::<i32>().ok()).collect();
-
-
-    //.remove_at(0)
-    //.map(|x| x.parse::<i32>().unwrap()).collect();
-    
-    //println!("{:?}", values)
-
-
+/* Battery saves are flushed every 5 seconds if they changed */
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
 
+const KEYMAP: [(VirtualKeyCode, gameboy::Button); 8] = [
+    (VirtualKeyCode::Z, gameboy::Button::A),
+    (VirtualKeyCode::X, gameboy::Button::B),
+    (VirtualKeyCode::Back, gameboy::Button::Select),
+    (VirtualKeyCode::Return, gameboy::Button::Start),
+    (VirtualKeyCode::Right, gameboy::Button::Right),
+    (VirtualKeyCode::Left, gameboy::Button::Left),
+    (VirtualKeyCode::Up, gameboy::Button::Up),
+    (VirtualKeyCode::Down, gameboy::Button::Down),
+];
 
+fn main() {
     env_logger::init();
 
-    let bios = std::fs::read("./src/roms/bios.gb").unwrap();
-    let rom: Vec<u8> = std::fs::read("./src/roms/Tetris.gb").unwrap();
-    //let rom: Vec<u8> = std::fs::read("./src/roms/opus5.gb").unwrap();
-    //let rom: Vec<u8> = std::fs::read("./src/roms/drmario.gb").unwrap();
-    //let rom: Vec<u8> = std::fs::read("./src/roms/blaarg/cpu_instrs/cpu_instrs.gb").unwrap();
-    //let mut gb = cpu::GameboyCPU::new(bios, rom).unwrap();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 2 && args[1] == "--disassemble" {
+        if let Err(e) = disassemble_command(&args[2..]) {
+            error!("Disassembly failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let rom = "./src/roms/Tetris.gb";
+    //let rom = "./src/roms/opus5.gb";
+    //let rom = "./src/roms/drmario.gb";
+    //let rom = "./src/roms/blaarg/cpu_instrs/cpu_instrs.gb";
+    let mut gb = match gameboy::GBEmulator::from_files("./src/roms/bios.gb", rom) {
+        Ok(gb) => gb,
+        Err(e) => {
+            error!("Failed to load {}: {}", rom, e);
+            std::process::exit(1);
+        }
+    };
+    info!("Loaded {}", gb.cart);
+
+    /* Battery saves live next to the ROM.  If one exists but can't be read
+     * then saving is turned off rather than risk overwriting it. */
+    let mut save_path = None;
+    let mut last_save = Vec::new();
+    if gb.cart.has_battery() {
+        let path = Path::new(rom).with_extension("sav");
+        match gb.cart.load_save(&path) {
+            Ok(()) => {
+                last_save = gb.cart.save_data();
+                save_path = Some(path);
+            },
+            Err(e) => error!("Failed to load {}, saving is disabled: {}", path.display(), e),
+        }
+    }
 
-    let mut gb = gameboy::GBEmulator::new(bios, rom);
+    /* The effect is rebuilt as gamepads come and go, so the callback
+     * shares it with the event loop */
+    let mut gilrs = Gilrs::new().map_err(|e| error!("Gamepad support unavailable: {}", e)).ok();
+    let rumble = Rc::new(RefCell::new(gilrs.as_mut().and_then(rumble_effect)));
+    {
+        let rumble = rumble.clone();
+        gb.on_rumble(move |on| {
+            if let Some(effect) = rumble.borrow().as_ref() {
+                let _ = if on { effect.play() } else { effect.stop() };
+            }
+        });
+    }
     
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
     let window = {
         let size = LogicalSize::new(SCREEN_WIDTH as f64, SCREEN_HEIGHT as f64);
         WindowBuilder::new()
-            .with_title("Rusty GB")
+            .with_title(if gb.cart.title.is_empty() { "Rusty GB" } else { &gb.cart.title })
             .with_inner_size(size)
             .with_min_inner_size(size)
             .build(&event_loop)
@@ -57,17 +107,16 @@ fn main() {
         Pixels::new(SCREEN_WIDTH, SCREEN_HEIGHT, surface_texture).unwrap()
     };
 
-    loop {
-        let cycles = gb.cpu_run_op();
-        gb.gpu_run(cycles);
-        gb.timers_run(cycles);
-        gb.handle_irqs();
-        /*if gb.regs.pc == 0x8E {
-            break;
-        }*/
-    }
-
+    let mut locked_reported = false;
+    let mut last_flush = Instant::now();
     event_loop.run(move |event, _, control_flow| {
+        if let Event::LoopDestroyed = event {
+            if let Some(path) = &save_path {
+                flush_save(&gb.cart, path, &mut last_save);
+            }
+            return;
+        }
+
         // Draw the current frame
         if let Event::RedrawRequested(_) = event {
             let frame = pixels.get_frame();
@@ -101,17 +150,90 @@ fn main() {
                 pixels.resize(size.width, size.height);
             }
 
+            /* gilrs only updates its gamepad state as events are read */
+            if let Some(gilrs) = gilrs.as_mut() {
+                while let Some(event) = gilrs.next_event() {
+                    if let EventType::Connected = event.event {
+                        *rumble.borrow_mut() = rumble_effect(gilrs);
+                    }
+                }
+            }
+
+            for (key, button) in KEYMAP.iter() {
+                gb.set_button(*button, input.key_held(*key));
+            }
+
+            /* The mouse position tilts MBC7 carts, up to 1g at the edges of
+             * the window */
+            if let Some((x, y)) = input.mouse() {
+                let size = window.inner_size();
+                gb.set_tilt(x / size.width as f32 * 2.0 - 1.0, y / size.height as f32 * 2.0 - 1.0);
+            }
+
+            if let Err(e) = gb.run_frame() {
+                if !locked_reported {
+                    error!("{}", e);
+                    locked_reported = true;
+                }
+            }
+
+            if last_flush.elapsed() >= SAVE_INTERVAL {
+                last_flush = Instant::now();
+                if let Some(path) = &save_path {
+                    flush_save(&gb.cart, path, &mut last_save);
+                }
+            }
+
             window.request_redraw();
         }
-
-       /* let mut framecycles = 0;
-        while framecycles < 70224 {
-            let cycles = gb.cpu_run_op();
-            gb.gpu_run(cycles);
-            gb.timers_run(cycles);
-            gb.handle_irqs();
-            
-            framecycles += cycles;
-        }*/
     });
 }
+
+/* Writes the battery save if it changed since it was last written */
+fn flush_save(cart: &gameboy::Cartridge, path: &Path, last_save: &mut Vec<u8>) {
+    let data = cart.save_data();
+    if data == *last_save {
+        return;
+    }
+    match cart.write_save(path) {
+        Ok(()) => *last_save = data,
+        Err(e) => error!("Failed to write {}: {}", path.display(), e),
+    }
+}
+
+/* A continuous rumble on every gamepad that supports it, started and
+ * stopped as the cartridge switches its motor */
+fn rumble_effect(gilrs: &mut Gilrs) -> Option<Effect> {
+    let gamepads: Vec<_> = gilrs.gamepads()
+        .filter(|(_, gamepad)| gamepad.is_ff_supported())
+        .map(|(id, _)| id)
+        .collect();
+    if gamepads.is_empty() {
+        return None;
+    }
+
+    EffectBuilder::new()
+        .add_effect(BaseEffect {
+            kind: BaseEffectType::Strong { magnitude: 0xC000 },
+            ..Default::default()
+        })
+        .gamepads(&gamepads)
+        .finish(gilrs)
+        .map_err(|e| error!("Failed to set up rumble: {}", e))
+        .ok()
+}
+
+/* rusty-gbe --disassemble <rom> [first bank] [last bank] [output.asm]
+ * Writes the listing to stdout if no output file is given */
+fn disassemble_command(args: &[String]) -> Result<(), gameboy::EmulatorError> {
+    let rom = std::fs::read(&args[0])?;
+    let first_bank = args.get(1).and_then(|x| x.parse().ok()).unwrap_or(0);
+    let last_bank = args.get(2).and_then(|x| x.parse().ok()).unwrap_or(usize::MAX);
+
+    let listing = gameboy::disassemble_rom(&rom, first_bank..=last_bank);
+    match args.get(3) {
+        Some(path) => std::fs::write(path, listing)?,
+        None => print!("{}", listing),
+    }
+    Ok(())
+}